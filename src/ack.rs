@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::io::{stdout, Write};
-use std::thread;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use rayon::prelude::*;
 
 pub fn pure_ack(a: u16, b: u16, c: u16) -> u16 {
@@ -14,6 +15,26 @@ pub fn pure_ack(a: u16, b: u16, c: u16) -> u16 {
     pure_ack((a + 32767) % 32768, intermediate , c)
 }
 
+/// `pure_ack` with its own stack of pending `a`s in place of recursion, so it can't overflow the
+/// native stack however deep it goes.
+pub fn iter_ack(a: u16, b: u16, c: u16) -> u16 {
+    let mut pending = vec![a];
+    let mut b = b;
+    while let Some(a) = pending.pop() {
+        if a == 0 {
+            b = (b + 1) % 32768;
+        } else if b == 0 {
+            pending.push(a - 1);
+            b = c;
+        } else {
+            pending.push(a - 1);
+            pending.push(a);
+            b -= 1;
+        }
+    }
+    b
+}
+
 pub fn memo_ack(memo: &mut HashMap<(u16, u16), u16>, a: u16, b: u16, c: u16) -> u16 {
     if let Some(ans) = memo.get(&(a, b)) {
         return *ans;
@@ -31,20 +52,161 @@ pub fn memo_ack(memo: &mut HashMap<(u16, u16), u16>, a: u16, b: u16, c: u16) ->
     let intermediate = memo_ack(memo,a, (b + 32767) % 32768, c);
     let ret = memo_ack(memo,(a + 32767) % 32768, intermediate, c);
     memo.insert((a, b), ret);
-    return ret;
+    ret
 }
 
-pub fn search() {
-    rayon::ThreadPoolBuilder::new()
-        .stack_size(1000000000).build_global().unwrap();
-    (1..32768_u16).into_par_iter().for_each(|i| {
-        if i % 32 == 0 {
-            print!(".");
-            let _ = stdout().flush();
-        }
-        if memo_ack(&mut HashMap::new(), 4, 1, i) == 6 {
-            println!("Found {}", i);
-        }
+/// `memo_ack` for a single `c`, memoised in a dense table indexed by `(a, b)` rather than a
+/// `HashMap`. `a` never grows during the recursion, so the table needs `a + 1` rows of 32768.
+///
+/// # Panics
+///
+/// If `b` or `c` is 32768 or more, as the VM's registers never are, since they'd fall outside
+/// the table.
+pub fn dense_ack(a: u16, b: u16, c: u16) -> u16 {
+    assert!(b < 32768 && c < 32768, "dense_ack needs b and c below 32768, got {} and {}", b, c);
+    let mut memo = vec![None; (usize::from(a) + 1) * 32768];
+    dense_ack_with(&mut memo, a, b, c)
+}
+
+fn dense_ack_with(memo: &mut [Option<u16>], a: u16, b: u16, c: u16) -> u16 {
+    let key = usize::from(a) * 32768 + usize::from(b);
+    if let Some(ans) = memo[key] {
+        return ans;
+    }
+    let ret = if a == 0 {
+        (b + 1) % 32768
+    } else if b == 0 {
+        dense_ack_with(memo, (a + 32767) % 32768, c, c)
+    } else {
+        let intermediate = dense_ack_with(memo, a, (b + 32767) % 32768, c);
+        dense_ack_with(memo, (a + 32767) % 32768, intermediate, c)
+    };
+    memo[key] = Some(ret);
+    ret
+}
+
+/// Prints every `c` in 1..32768 for which `ack(4, 1, c)` is `target`, with dots for progress.
+pub fn search(target: u16) {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .stack_size(1000000000).build().unwrap();
+    pool.install(|| {
+        (1..32768_u16).into_par_iter().for_each(|i| {
+            if i % 32 == 0 {
+                print!(".");
+                let _ = stdout().flush();
+            }
+            if dense_ack(4, 1, i) == target {
+                println!("Found {}", i);
+            }
+        })
     });
 }
 
+/// `(c, ack(4, 1, c))` for every `c` in `cs`, in order. Runs on its own pool, as the recursion
+/// needs far more stack than rayon's global pool gives each thread.
+pub fn ack_table(cs: Range<u16>) -> Vec<(u16, u16)> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .stack_size(1000000000).build().unwrap();
+    pool.install(|| {
+        cs.into_par_iter()
+            .map(|c| (c, dense_ack(4, 1, c)))
+            .collect()
+    })
+}
+
+/// The first `c` for which `ack(4, 1, c)` is 6, which is the setting the teleporter wants in r7.
+pub fn find_teleporter_setting() -> Option<u16> {
+    find_c(6)
+}
+
+/// `find_teleporter_setting`, giving up with `None` once `running` is cleared.
+pub fn find_teleporter_setting_while(running: &AtomicBool) -> Option<u16> {
+    find_c_while(6, running)
+}
+
+/// The first `c` in 1..32768 for which `ack(4, 1, c)` is `target`.
+pub fn find_c(target: u16) -> Option<u16> {
+    find_c_while(target, &AtomicBool::new(true))
+}
+
+/// `find_c`, giving up with `None` once `running` is cleared. Every worker checks it before
+/// starting on another `c`, so they all stop soon after.
+pub fn find_c_while(target: u16, running: &AtomicBool) -> Option<u16> {
+    find_c_in(target, 1..32768, running)
+}
+
+fn find_c_in(target: u16, cs: Range<u16>, running: &AtomicBool) -> Option<u16> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .stack_size(1000000000).build().unwrap();
+    pool.install(|| {
+        cs.into_par_iter()
+            .find_first(|&c| running.load(Ordering::SeqCst) && dense_ack(4, 1, c) == target)
+    }).filter(|_| running.load(Ordering::SeqCst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ack_table_finds_the_teleporter_value() {
+        let table = ack_table(25733..25736);
+        assert_eq!(table.iter().map(|&(c, _)| c).collect::<Vec<_>>(), vec![25733, 25734, 25735]);
+        assert_eq!(table[1], (25734, 6));
+        assert_eq!(table.iter().filter(|&&(_, r)| r == 6).count(), 1);
+    }
+
+    #[test]
+    fn iter_ack_matches_memo_ack() {
+        for a in 0..=3 {
+            for b in 0..4 {
+                for c in 0..4 {
+                    assert_eq!(iter_ack(a, b, c), memo_ack(&mut HashMap::new(), a, b, c), "ack({}, {}, {})", a, b, c);
+                }
+            }
+        }
+        assert_eq!(iter_ack(0, 32767, 0), 0);
+    }
+
+    #[test]
+    fn dense_ack_matches_memo_ack() {
+        // Both recurse far deeper than a test thread's stack allows.
+        std::thread::Builder::new().stack_size(1000000000).spawn(|| {
+            for a in 0..=4 {
+                for b in 0..4 {
+                    for c in [0, 1, 2, 100, 25734, 32767].iter().cloned() {
+                        assert_eq!(dense_ack(a, b, c), memo_ack(&mut HashMap::new(), a, b, c), "ack({}, {}, {})", a, b, c);
+                    }
+                }
+            }
+        }).unwrap().join().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "dense_ack needs b and c below 32768")]
+    fn dense_ack_rejects_b_past_the_table() {
+        dense_ack(0, 40000, 1);
+    }
+
+    #[test]
+    fn find_c_finds_the_first_match() {
+        let target = ack_table(1..2)[0].1;
+        assert_eq!(find_c(target), Some(1));
+    }
+
+    #[test]
+    fn find_c_finds_the_teleporter_setting_near_it() {
+        assert_eq!(find_c_in(6, 25730..25740, &AtomicBool::new(true)), Some(25734));
+    }
+
+    #[test]
+    #[ignore = "searches most of the register range; quick only with --release"]
+    fn find_c_finds_the_teleporter_setting() {
+        assert_eq!(find_c(6), Some(25734));
+    }
+
+    #[test]
+    fn search_gives_up_when_stopped() {
+        assert_eq!(find_teleporter_setting_while(&AtomicBool::new(false)), None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use crate::{Op, Vm};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    WrongArgCount { line: usize, op: Op, expected: u16, found: usize },
+    BadOperand { line: usize, operand: String },
+    UndefinedLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+}
+
+/// Looks up an `Op` by its (case-insensitive) name, as printed by the disassembler.
+fn mnemonic(name: &str) -> Option<Op> {
+    (0..22_u16)
+        .filter_map(|i| i.try_into().ok())
+        .find(|op: &Op| format!("{:?}", op).eq_ignore_ascii_case(name))
+}
+
+/// A decimal or `0x` hex number.
+fn number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// A literal 0..32767, a register `r0`..`r7` or its number 32768..32775, or a label, written
+/// `@name` or just `name`.
+fn operand(s: &str, labels: &HashMap<&str, u16>) -> Option<u16> {
+    if let Some(reg) = s.strip_prefix('r').and_then(|r| r.parse::<u16>().ok()) {
+        return Some(reg).filter(|&r| r < 8).map(|r| 32768 + r);
+    }
+    match number(s) {
+        Some(v) => Some(v).filter(|&v| v < 32776),
+        None => labels.get(s.strip_prefix('@').unwrap_or(s)).cloned(),
+    }
+}
+
+/// Whether `s` can name a label: letters, digits and `_`, not starting with a digit.
+fn is_label(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The tokens of each line with its number, with comments, any leading `@address` (as the
+/// disassembler prints) and blank lines dropped.
+fn lines(src: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    src.lines().enumerate().filter_map(|(line, text)| {
+        let text = text.split("//").next().unwrap_or("");
+        let mut tokens: Vec<&str> = text.split_whitespace().collect();
+        if tokens.first().and_then(|t| t.strip_prefix('@')).and_then(number).is_some() {
+            tokens.remove(0);
+        }
+        if tokens.is_empty() { None } else { Some((line + 1, tokens)) }
+    })
+}
+
+/// Assembles one instruction per line, e.g. `set r0 6` or `call 6027`, into program words to be
+/// loaded at address 0.
+///
+/// Mnemonics are the `Op` names; operands are decimal or `0x` hex literals, `r0`..`r7`, or labels.
+/// A line can start with `name:` to label the address it's at, and a line holding a lone number is
+/// emitted as a raw data word. Blank lines and anything after `//` are ignored, as is a leading
+/// `@address`, so the disassembler's output reassembles.
+pub fn assemble(src: &str) -> Result<Vec<u16>, AsmError> {
+    assemble_at(src, 0)
+}
+
+/// `assemble`, for words to be loaded at `origin`, which labels are counted from.
+pub fn assemble_at(src: &str, origin: u16) -> Result<Vec<u16>, AsmError> {
+    // First pass: strip label definitions and find where each line's words will go.
+    let mut labels = HashMap::new();
+    let mut program = Vec::new();
+    let mut addr = origin;
+    for (line, mut tokens) in lines(src) {
+        if let Some(label) = tokens[0].strip_suffix(':').filter(|l| is_label(l)) {
+            if labels.insert(label, addr).is_some() {
+                return Err(AsmError::DuplicateLabel { line, label: label.to_string() });
+            }
+            tokens.remove(0);
+        }
+        let (first, args) = match tokens.split_first() {
+            Some(x) => x,
+            None => continue,
+        };
+        addr = addr.wrapping_add(match mnemonic(first) {
+            Some(op) => 1 + Vm::arg_count(op),
+            None => 1,
+        });
+        program.push((line, *first, args.to_vec()));
+    }
+    // Second pass: emit the words, now every label is known.
+    let mut words = Vec::new();
+    for (line, first, args) in program {
+        if let Some(word) = number(first) {
+            if args.is_empty() {
+                words.push(word);
+                continue;
+            }
+        }
+        let op = mnemonic(first).ok_or_else(|| AsmError::UnknownMnemonic { line, mnemonic: first.to_string() })?;
+        let expected = Vm::arg_count(op);
+        if args.len() != usize::from(expected) {
+            return Err(AsmError::WrongArgCount { line, op, expected, found: args.len() });
+        }
+        words.push(op as u16);
+        for arg in args {
+            let word = operand(arg, &labels).ok_or_else(|| {
+                let label = arg.strip_prefix('@').unwrap_or(arg);
+                if is_label(label) && !(arg.starts_with('r') && arg[1..].parse::<u16>().is_ok()) {
+                    AsmError::UndefinedLabel { line, label: label.to_string() }
+                } else {
+                    AsmError::BadOperand { line, operand: arg.to_string() }
+                }
+            })?;
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_mnemonics_registers_and_data() {
+        let src = "set r0 6 // force the check\nNOP\n\ncall 6027\n1234\n";
+        assert_eq!(assemble(src), Ok(vec![1, 32768, 6, 21, 17, 6027, 1234]));
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(assemble("nop\nfrob r0"),
+                   Err(AsmError::UnknownMnemonic { line: 2, mnemonic: "frob".to_string() }));
+        assert_eq!(assemble("set r0"),
+                   Err(AsmError::WrongArgCount { line: 1, op: Op::Set, expected: 2, found: 1 }));
+        assert_eq!(assemble("set r8 1"), Err(AsmError::BadOperand { line: 1, operand: "r8".to_string() }));
+        assert_eq!(assemble("out 32776"), Err(AsmError::BadOperand { line: 1, operand: "32776".to_string() }));
+        assert_eq!(assemble("jmp @nowhere"),
+                   Err(AsmError::UndefinedLabel { line: 1, label: "nowhere".to_string() }));
+        assert_eq!(assemble("a: nop\na: nop"),
+                   Err(AsmError::DuplicateLabel { line: 2, label: "a".to_string() }));
+    }
+
+    #[test]
+    fn resolves_labels_forwards_and_backwards() {
+        let src = "start: jt r0 @done\n  add r1 r1 0x1\n  jmp @start\ndone:\n  halt\n";
+        assert_eq!(assemble(src), Ok(vec![7, 32768, 9, 9, 32769, 32769, 1, 6, 0, 0]));
+        assert_eq!(assemble_at("loop: jmp loop", 100), Ok(vec![6, 100]));
+    }
+
+    #[test]
+    fn reassembles_the_disassembled_challenge() {
+        let program = crate::load_program("doc/challenge.bin").unwrap();
+        let vm = Vm::new(&program);
+        let code = vm.reachable_code();
+        for (start, end) in crate::address_ranges(code.iter().cloned()) {
+            let listing = vm.disassemble_range(start, end + 1, &Default::default());
+            let words = assemble_at(&listing, start).unwrap();
+            assert_eq!(words, &program[usize::from(start)..=usize::from(end)], "in {}..={}", start, end);
+        }
+    }
+}
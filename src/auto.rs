@@ -0,0 +1,117 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use crate::{ack, coins, find_codes, vault, StepError, Vm, PARTIAL_SOLUTION};
+
+/// From the beach where the teleporter lands to the vault antechamber, picking up the journal.
+const WALK_TO_ORB: &str = "north\nnorth\nnorth\nnorth\nnorth\nnorth\nnorth\neast\ntake journal\nwest\nnorth\nnorth\ntake orb\n";
+
+/// Feeds `input` to the game and returns everything it printed until it next wants input.
+fn play(vm: &mut Vm, input: &str) -> Result<String, StepError> {
+    vm.push_input(input);
+    vm.run_to_input(Arc::new(AtomicBool::new(true)))?;
+    Ok(vm.take_output())
+}
+
+/// The code as it reads when seen in a mirror.
+fn mirrored(code: &str) -> String {
+    code.chars().rev()
+        .map(|c| match c {
+            'b' => 'd',
+            'd' => 'b',
+            'p' => 'q',
+            'q' => 'p',
+            c => c,
+        })
+        .collect()
+}
+
+/// The vault route as game commands.
+fn vault_commands(route: &str) -> String {
+    route.chars()
+        .map(|c| match c {
+            'N' => "north\n",
+            'E' => "east\n",
+            'S' => "south\n",
+            _ => "west\n",
+        })
+        .collect()
+}
+
+/// `PARTIAL_SOLUTION` with its coin placements replaced by the order `coins::solve` finds.
+fn route_to_teleporter() -> String {
+    let is_coin_use = |line: &&str| line.starts_with("use ") && line.ends_with(" coin");
+    let lines: Vec<&str> = PARTIAL_SOLUTION.lines().collect();
+    let first = lines.iter().position(is_coin_use).expect("no coins used in PARTIAL_SOLUTION");
+    let last = lines.iter().rposition(is_coin_use).unwrap();
+    let order = coins::solve().expect("no order of the coins balances the equation");
+    let mut route = String::new();
+    for line in &lines[..first] {
+        route += &format!("{}\n", line);
+    }
+    for coin in order {
+        route += &format!("use {}\n", coin);
+    }
+    for line in &lines[last + 1..] {
+        route += &format!("{}\n", line);
+    }
+    route
+}
+
+fn labelled(label: &str, codes: Vec<String>) -> impl Iterator<Item = (String, String)> + '_ {
+    codes.into_iter().map(move |code| (label.to_string(), code))
+}
+
+/// Plays the whole challenge from boot, using the solvers for each puzzle, and returns every code
+/// found, labelled by where it turned up.
+pub fn solve(program: &[u16]) -> Result<Vec<(String, String)>, StepError> {
+    let mut vm = Vm::new(program);
+    vm.live_output = false;
+    vm.set_headless(true);
+    let boot = play(&mut vm, "")?;
+    let mut codes: Vec<(String, String)> = ["welcome", "self-test"].iter()
+        .map(|l| l.to_string())
+        .zip(find_codes(&boot))
+        .collect();
+    codes.extend(labelled("tablet", find_codes(&play(&mut vm, "take tablet\nuse tablet\n")?)));
+    codes.extend(labelled("twisty passages", find_codes(&play(&mut vm, &route_to_teleporter())?)));
+    codes.extend(labelled("teleporter (headquarters)", find_codes(&play(&mut vm, "use teleporter\n")?)));
+    let r7 = ack::find_teleporter_setting().expect("no r7 makes the confirmation return 6");
+    vm.patch_teleporter(r7);
+    codes.extend(labelled("teleporter (beach)", find_codes(&play(&mut vm, "use teleporter\n")?)));
+    play(&mut vm, WALK_TO_ORB)?;
+    let route = vault::all_shortest_solutions().into_iter().next().expect("no route through the vault");
+    play(&mut vm, &vault_commands(&route))?;
+    let mirror = find_codes(&play(&mut vm, "vault\ntake mirror\nuse mirror\n")?);
+    codes.extend(labelled("mirror", mirror.iter().map(|c| mirrored(c)).collect()));
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrored_flips_and_reverses() {
+        assert_eq!(mirrored("TOUopp8OMbdp"), "qbdMO8qqoUOT");
+    }
+
+    #[test]
+    fn route_to_teleporter_uses_the_solved_coin_order() {
+        let route = route_to_teleporter();
+        let coins: Vec<&str> = route.lines().filter(|l| l.ends_with(" coin") && l.starts_with("use ")).collect();
+        let expected: Vec<String> = coins::solve().unwrap().iter().map(|c| format!("use {}", c)).collect();
+        assert_eq!(coins, expected);
+        assert_eq!(route.lines().count(), PARTIAL_SOLUTION.lines().count());
+        assert!(route.starts_with("doorway\n") && route.ends_with("north\ntake teleporter\n"));
+    }
+
+    #[test]
+    #[ignore = "needs doc/challenge.bin and minutes of teleporter search"]
+    fn solves_the_real_binary() {
+        let data = std::fs::read("doc/challenge.bin").unwrap();
+        let program: Vec<u16> = data.chunks(2).map(|s| u16::from(s[1]) << 8 | u16::from(s[0])).collect();
+        let codes = solve(&program).unwrap();
+        assert_eq!(codes.len(), 7);
+        assert_eq!(codes.last().unwrap(), &("mirror".to_string(), "qbdMO8qqoUOT".to_string()));
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use crate::{MemoryChange, RunStatus, StepError, Vm};
+
+/// Where two scripts of game input first make the game say different things.
+#[derive(Debug)]
+pub struct Divergence<'a> {
+    /// Index of the first line after which the output differed.
+    pub line: usize,
+    /// That line from each script, or `None` where the script had already ended.
+    pub commands: (Option<String>, Option<String>),
+    /// What the game printed in response to each.
+    pub outputs: (String, String),
+    /// Addresses whose overlay differs between the two machines at that point.
+    pub memory: Vec<(u16, MemoryChange)>,
+    /// Both machines, just after the divergent line.
+    pub states: (Vm<'a>, Vm<'a>),
+}
+
+/// Why a bisection couldn't finish.
+#[derive(Debug)]
+pub enum BisectError {
+    Step(StepError),
+    /// A run stopped for some other reason than halting or wanting input, so its output would be
+    /// cut short.
+    Stopped(RunStatus),
+}
+
+impl From<StepError> for BisectError {
+    fn from(e: StepError) -> Self {
+        BisectError::Step(e)
+    }
+}
+
+impl std::fmt::Display for BisectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BisectError::Step(e) => write!(f, "{}", e),
+            BisectError::Stopped(status) => write!(f, "a run stopped early: {:?}", status),
+        }
+    }
+}
+
+fn run(vm: &mut Vm) -> Result<(), BisectError> {
+    match vm.run_to_input(Arc::new(AtomicBool::new(true)))? {
+        RunStatus::WaitingForInput | RunStatus::Halted => Ok(()),
+        status => Err(BisectError::Stopped(status)),
+    }
+}
+
+fn feed(vm: &mut Vm, line: Option<&str>) -> Result<String, BisectError> {
+    if let Some(line) = line {
+        vm.push_input(line);
+        vm.push_input("\n");
+        run(vm)?;
+    }
+    Ok(vm.take_output())
+}
+
+/// Plays scripts `a` and `b` a line at a time from `boot`, and reports the first line after which
+/// their output differs. Output before the first line is always the same, so isn't compared.
+/// Returns `None` if the scripts never diverge. Breakpoints and watches on `boot` are ignored.
+pub fn first_divergence<'a>(boot: &Vm<'a>, a: &str, b: &str) -> Result<Option<Divergence<'a>>, BisectError> {
+    let mut vm_a = boot.clone();
+    vm_a.live_output = false;
+    let _ = vm_a.set_log(None);
+    vm_a.set_trace(false);
+    vm_a.set_headless(true);
+    vm_a.breakpoints.clear();
+    vm_a.watches.clear();
+    run(&mut vm_a)?;
+    vm_a.take_output();
+    let mut vm_b = vm_a.clone();
+    let (mut lines_a, mut lines_b) = (a.lines(), b.lines());
+    for line in 0.. {
+        let (cmd_a, cmd_b) = (lines_a.next(), lines_b.next());
+        if cmd_a.is_none() && cmd_b.is_none() {
+            break;
+        }
+        let out_a = feed(&mut vm_a, cmd_a)?;
+        let out_b = feed(&mut vm_b, cmd_b)?;
+        if out_a != out_b {
+            return Ok(Some(Divergence {
+                line,
+                commands: (cmd_a.map(String::from), cmd_b.map(String::from)),
+                outputs: (out_a, out_b),
+                memory: vm_a.diff_memory(&vm_b),
+                states: (vm_a, vm_b),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoes each character read, and keeps the last one at 10.
+    const ECHO: [u16; 11] = [20, 32768, 19, 32768, 16, 10, 32768, 6, 0, 0, 0];
+
+    #[test]
+    fn finds_the_first_line_with_different_output() {
+        let boot = Vm::new(&ECHO);
+        let found = first_divergence(&boot, "look\nnorth\nsouth\n", "look\nnorht\nsouth\n").unwrap().unwrap();
+        assert_eq!(found.line, 1);
+        assert_eq!(found.commands, (Some("north".to_string()), Some("norht".to_string())));
+        assert_eq!(found.outputs, ("north\n".to_string(), "norht\n".to_string()));
+        assert!(first_divergence(&boot, "a\nb\n", "a\nb\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn breakpoints_and_watches_are_ignored() {
+        let mut boot = Vm::new(&ECHO);
+        boot.breakpoints.insert(2);
+        boot.watches.insert(10);
+        let found = first_divergence(&boot, "ab\n", "ac\n").unwrap().unwrap();
+        assert_eq!(found.outputs, ("ab\n".to_string(), "ac\n".to_string()));
+    }
+
+    #[test]
+    fn a_shorter_script_diverges_where_it_ends() {
+        let boot = Vm::new(&ECHO);
+        let found = first_divergence(&boot, "a\nb\n", "a\n").unwrap().unwrap();
+        assert_eq!(found.line, 1);
+        assert_eq!(found.commands.1, None);
+        assert_eq!(found.outputs.1, "");
+    }
+}
@@ -0,0 +1,37 @@
+use itertools::Itertools;
+
+/// The coins found around the ruins, with the values their shapes stand for.
+pub const COINS: [(&str, i32); 5] = [
+    ("red coin", 2),
+    ("corroded coin", 3),
+    ("shiny coin", 5),
+    ("concave coin", 7),
+    ("blue coin", 9),
+];
+
+/// The equation on the monument, with a coin's value in each slot.
+fn balances(slots: &[i32]) -> bool {
+    let (a, b, c, d, e) = (slots[0], slots[1], slots[2], slots[3], slots[4]);
+    a + b * c.pow(2) + d.pow(3) - e == 399
+}
+
+/// The coins in the order to place them in the slots, found by trying every order.
+pub fn solve() -> Option<Vec<&'static str>> {
+    COINS.iter()
+        .permutations(COINS.len())
+        .find(|order| balances(&order.iter().map(|(_, v)| *v).collect_vec()))
+        .map(|order| order.into_iter().map(|(name, _)| *name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_known_order() {
+        let order = solve().unwrap();
+        let values = order.iter().map(|name| COINS.iter().find(|(n, _)| n == name).unwrap().1).collect_vec();
+        assert_eq!(values, vec![9, 2, 5, 7, 3]);
+        assert_eq!(order, vec!["blue coin", "red coin", "shiny coin", "concave coin", "corroded coin"]);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::debugger::{self, Debugger};
+
+/// Runs a command, given the words of its line with the command name first.
+pub type Handler = fn(&mut Debugger, &[&str]);
+
+/// A debugger command, as listed by `help`.
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub about: &'static str,
+    pub handler: Handler,
+}
+
+const fn cmd(name: &'static str, args: &'static str, about: &'static str, handler: Handler) -> CommandInfo {
+    CommandInfo { name, args, about, handler }
+}
+
+/// Every debugger command. The prompt only treats a line as a command if its first word is named
+/// here; anything else is sent to the game.
+pub const COMMANDS: &[CommandInfo] = &[
+    cmd("help", "", "list debugger commands", debugger::help),
+    cmd("quit", "", "leave the debugger", debugger::quit),
+    cmd("input", "", "print everything typed so far", debugger::input),
+    cmd("backtrace", "", "the stack top first, with return addresses shown as calls", debugger::backtrace),
+    cmd("regs", "", "print r0..r7", debugger::regs),
+    cmd("dump", "", "print the stack, IP and instruction count", debugger::dump),
+    cmd("diff", "<a> <b>", "compare memory, IP and stack between two saved steps or names", debugger::diff),
+    cmd("load", "<step|name>", "go back to a saved step or name", debugger::load),
+    cmd("name", "[slot]", "name the current state for load and diff, or list the names", debugger::name),
+    cmd("save", "<path>", "write the VM, but not the debugger's history, to a file", debugger::save),
+    cmd("restore", "<path>", "replace the VM with one written by save", debugger::restore),
+    cmd("session", "save|load <path>", "save or restore the whole debugger session", debugger::session),
+    cmd("get", "<addr|start..end>", "print memory, one word per line", debugger::get),
+    cmd("gets", "<addr> [len]", "print memory as a string, length-prefixed if no len", debugger::gets),
+    cmd("set", "<addr|rN> <value>", "poke a word of memory or a register", debugger::set),
+    cmd("undo-set", "", "revert the most recent set", debugger::undo_set),
+    cmd("findseq", "<v0> <v1> ...", "find every address holding this sequence of words", debugger::findseq),
+    cmd("stats", "[reset]", "how often each op has executed, or start counting afresh", debugger::stats),
+    cmd("profile", "on|off", "count executions of each address (slows running down)", debugger::profile),
+    cmd("selfmod", "on|off", "warn when Wmem writes over code that has already run", debugger::selfmod),
+    cmd("hotspots", "[n]", "the n most executed addresses since profiling started", debugger::hotspots),
+    cmd("checksum", "", "checksum of the program as it is now", debugger::checksum),
+    cmd("unmapped", "error|zero", "what reading an address beyond the program does", debugger::unmapped),
+    cmd("echo", "on|off", "whether the game's output is shown as it's printed", debugger::echo),
+    cmd("flush", "line|never|<chars>", "when the game's output is flushed to the terminal", debugger::flush),
+    cmd("log", "<path>|off", "append the game's output to a file as well", debugger::log),
+    cmd("trace", "on|off|<path>", "print each instruction as it runs, or write them to a file", debugger::trace),
+    cmd("writes", "[on|off]", "track, or list, addresses written during the last run", debugger::writes),
+    cmd("solve", "", "feed the known route up to the teleporter", debugger::solve),
+    cmd("solve-coins", "", "print the order to use the coins in at the monument", debugger::solve_coins),
+    cmd("try", "<command>", "run a game command on a copy, without advancing", debugger::try_command),
+    cmd("break", "[addr]", "stop before executing addr, or list breakpoints", debugger::breakpoint),
+    cmd("unbreak", "<addr>", "remove a breakpoint", debugger::unbreak),
+    cmd("watch", "[addr|rN]", "stop after any change to addr, or list watched addresses", debugger::watch),
+    cmd("unwatch", "<addr|rN>", "stop watching an address", debugger::unwatch),
+    cmd("run-to-halt", "", "run until the program halts or wants input, ignoring breakpoints and watches", debugger::run_to_halt),
+    cmd("bisect", "<a> <b>", "find the first line where two scripts' output differs", debugger::bisect),
+    cmd("step", "[n]", "execute n instructions (default 1), listing each", debugger::step),
+    cmd("next", "", "step one instruction, running a call through to its return", debugger::next),
+    cmd("record", "<depth>", "keep the last depth steps for undo (slow; 0 to stop)", debugger::record),
+    cmd("undo", "", "take back the last recorded step", debugger::undo),
+    cmd("script", "<path>", "run each line of a file as if typed, skipping blanks and # comments", debugger::script),
+    cmd("reset", "", "restart the game from the program as loaded", debugger::reset),
+    cmd("replay", "<path>", "restart the VM and feed it a script of game input", debugger::replay),
+    cmd("step-back", "[n]", "go back n instructions by replaying from a snapshot", debugger::step_back),
+    cmd("patch-tele", "", "skip the teleporter's confirmation, with r7 = 25734", debugger::patch_tele),
+    cmd("teleport", "", "find r7 for the teleporter, patch its check and use it", debugger::teleport),
+    cmd("acktable", "<start> <end>", "tabulate the teleporter function for r7 in start..end", debugger::acktable),
+    cmd("find-ack", "", "look for the teleporter's confirmation routine", debugger::find_ack),
+    cmd("xref", "<addr>", "list the calls and jumps to addr", debugger::xref),
+    cmd("reachable", "", "list address ranges reachable from 0", debugger::reachable),
+    cmd("coverage", "", "which addresses have been executed, and which never have", debugger::coverage),
+    cmd("asm", "<addr>", "assemble lines typed next into memory at addr", debugger::asm),
+    cmd("annotate", "on|off", "comment Rmem/Wmem in listings with what they access", debugger::annotate),
+    cmd("label", "[<addr> <name>]", "name an address in disassembly, or list the names", debugger::label),
+    cmd("disasm", "[<start> <end>]", "disassemble start..end, or the whole of memory", debugger::disasm),
+    cmd("dis-save", "<path> [<from> <to>]", "write disassembly to a file", debugger::dis_save),
+    cmd("view", "[addr]", "list instructions from addr (default: the IP)", debugger::view),
+    cmd("follow", "[operand]", "move the view to the jump target of its first instruction", debugger::follow),
+    cmd("back", "", "return the view to where it was before the last follow", debugger::back),
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Splits a line into its first word and the rest, both trimmed.
+pub fn split_command(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim_start()),
+        None => (line, ""),
+    }
+}
+
+pub fn help_text() -> String {
+    let width = COMMANDS.iter().map(|c| c.name.len() + c.args.len() + 1).max().unwrap_or(0);
+    let mut ans = String::from("Debugger commands (anything else goes to the game; 'try help' shows the game's own help):\n");
+    for c in COMMANDS {
+        let usage = format!("{} {}", c.name, c.args);
+        ans += &format!("  {:width$}  {}\n", usage, c.about, width = width);
+    }
+    ans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_command_trims_and_keeps_the_rest_whole() {
+        assert_eq!(split_command("try  take tablet\n"), ("try", "take tablet"));
+        assert_eq!(split_command("quit\n"), ("quit", ""));
+        assert_eq!(split_command("  \n"), ("", ""));
+    }
+
+    #[test]
+    fn command_names_are_unique() {
+        for (i, c) in COMMANDS.iter().enumerate() {
+            assert!(COMMANDS[i + 1..].iter().all(|d| d.name != c.name), "{} listed twice", c.name);
+        }
+    }
+}
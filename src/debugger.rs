@@ -0,0 +1,1234 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Instant;
+use itertools::Itertools;
+use crate::{ack, asm, bisect, coins, commands, session, teleporter};
+use crate::{address_ranges, find_codes, next_line, parse_addr, parse_addr_range, print_listing, push_checkpoint};
+use crate::{DecodedOp, DisasmOptions, FlushPolicy, RunStatus, StackEntry, StepError, StepOverStatus, UnmappedReads};
+use crate::{Snapshot, Vm};
+use crate::{MAX_REPLAY, MAX_SET_UNDO, PARTIAL_SOLUTION, SLOW_REPLAY, STEP_OVER_BUDGET};
+
+/// The state the prompt keeps between commands. Each command in `commands::COMMANDS` is a
+/// handler here, given the words of its line with the command name first.
+pub struct Debugger<'a> {
+    pub vm: Vm<'a>,
+    // The program as loaded, before any `flash_rom`, for `reset` and `replay` to start from.
+    original_rom: Cow<'a, [u16]>,
+    step_no: usize,
+    saves: HashMap<Snapshot, usize>,
+    by_step: HashMap<usize, Snapshot>,
+    // States tagged by `name`, which `load` and `diff` take in place of a step number.
+    names: HashMap<String, Snapshot>,
+    // The rom the latest snapshot was taken over, which the next shares if it hasn't changed.
+    shared_rom: Arc<[u16]>,
+    all_input: String,
+    checkpoints: VecDeque<Vm<'a>>,
+    // The disassembly view's current address and the addresses `back` returns to.
+    view_at: Option<u16>,
+    view_stack: Vec<u16>,
+    disasm_options: DisasmOptions,
+    // Address and previous value of each manual `set`, most recent last.
+    set_undo: VecDeque<(u16, Option<u16>)>,
+    running: Arc<AtomicBool>,
+    quit: Arc<AtomicBool>,
+    lines: Receiver<String>,
+    done: bool,
+}
+
+impl<'a> Debugger<'a> {
+    /// The VM is made headless, so that input only ever comes through `lines`. An `In` reading stdin
+    /// itself would race the thread feeding `lines`, and take pasted lines out of order.
+    pub fn new(mut vm: Vm<'a>, checkpoints: VecDeque<Vm<'a>>, running: Arc<AtomicBool>, quit: Arc<AtomicBool>,
+               lines: Receiver<String>) -> Self {
+        vm.set_headless(true);
+        Debugger {
+            original_rom: vm.rom.clone(),
+            step_no: 0,
+            saves: HashMap::new(),
+            by_step: HashMap::new(),
+            names: HashMap::new(),
+            shared_rom: Arc::from(&*vm.rom),
+            all_input: String::new(),
+            checkpoints,
+            view_at: None,
+            view_stack: Vec::new(),
+            disasm_options: DisasmOptions::default(),
+            set_undo: VecDeque::new(),
+            running,
+            quit,
+            lines,
+            done: false,
+            vm,
+        }
+    }
+
+    /// Prompts for and handles lines until `quit`, or until Ctrl-C is pressed at the prompt.
+    pub fn run(&mut self) {
+        while !self.done {
+            let first_seen = self.record_step();
+            let _output = self.vm.take_output();
+            let unmapped = self.vm.take_unmapped_reads();
+            if !unmapped.is_empty() {
+                println!("WARNING: read {} unmapped addresses as 0: {:?}", unmapped.len(), unmapped);
+            }
+            print!("{}", self.prompt(first_seen));
+            let _ = stdout().flush();
+            match next_line(&self.lines, &self.quit) {
+                Some(s) => self.handle_line(&s),
+                None => break,
+            }
+        }
+    }
+
+    /// Remembers the current state as this step's, unless it was seen before, and returns the step
+    /// it was first seen at.
+    fn record_step(&mut self) -> usize {
+        let snapshot = self.vm.snapshot(&mut self.shared_rom);
+        let first_seen = *self.saves.entry(snapshot.clone()).or_insert(self.step_no);
+        if first_seen == self.step_no {
+            self.by_step.insert(self.step_no, snapshot);
+        }
+        first_seen
+    }
+
+    /// The step number, when this state was first seen, and the instruction about to execute.
+    fn prompt(&self, first_seen: usize) -> String {
+        match self.vm.peek_instruction().filter(|_| self.vm.running) {
+            Some(ins) => format!("STEP {} (first seen {}) {}: ", self.step_no, first_seen,
+                                 self.vm.describe_instruction(&ins)),
+            None => format!("STEP {} (first seen {}): ", self.step_no, first_seen),
+        }
+    }
+
+    /// The state saved as step `key` if it's a number, or named `key` if not.
+    fn saved(&self, key: &str) -> Option<&Snapshot> {
+        match key.parse::<usize>() {
+            Ok(step) => self.by_step.get(&step),
+            Err(_) => self.names.get(key),
+        }
+    }
+
+    /// Puts the VM back to how it was loaded, with the original rom, and forgets the checkpoints
+    /// and `set`s made since.
+    fn restart(&mut self) {
+        self.vm.rom = self.original_rom.clone();
+        self.vm.reset();
+        self.checkpoints.clear();
+        self.set_undo.clear();
+        push_checkpoint(&mut self.checkpoints, &self.vm);
+    }
+
+    /// Runs the command named by the first word of `line`, or sends the line to the game if there
+    /// isn't one.
+    pub fn handle_line(&mut self, line: &str) {
+        self.all_input += line;
+        let (name, _) = commands::split_command(line);
+        match commands::lookup(name) {
+            Some(c) => (c.handler)(self, &line.split_whitespace().collect_vec()),
+            None => {
+                self.vm.push_input(line);
+                push_checkpoint(&mut self.checkpoints, &self.vm);
+                let status = self.vm.run_to_input(self.running.clone());
+                if status != Ok(RunStatus::WaitingForInput) {
+                    println!("{}", describe(status));
+                }
+                self.step_no += 1;
+            }
+        }
+    }
+}
+
+pub fn quit(d: &mut Debugger, _args: &[&str]) {
+    d.done = true;
+}
+
+pub fn diff(d: &mut Debugger, args: &[&str]) {
+    match (args.get(1), args.get(2)) {
+        (Some(a), Some(b)) => {
+            let (vma, vmb) = match (d.saved(a), d.saved(b)) {
+                (Some(vma), Some(vmb)) => (vma, vmb),
+                (None, _) => return println!("Unknown state: {}", a),
+                (_, None) => return println!("Unknown state: {}", b),
+            };
+            println!("Diffing {} and {}", a, b);
+            println!("Changed: ");
+            for (a, change) in vma.diff_memory(vmb) {
+                println!("  @{:?} = {:?} ==> {:?} ({:?})", a, vma.overlay_get(a), vmb.overlay_get(a), change);
+            }
+            if let Some((ip_a, ip_b)) = vma.diff_ip(vmb) {
+                println!("IP: {} ==> {}", ip_a, ip_b);
+            }
+            if let Some((from, rest_a, rest_b)) = vma.diff_stack(vmb) {
+                println!("Stack (depth {} ==> {}), from entry {}: {:?} ==> {:?}",
+                         from + rest_a.len(), from + rest_b.len(), from, rest_a, rest_b);
+            }
+        }
+        _ => println!("usage: diff <a> <b> (each a step number or a name)"),
+    }
+}
+
+pub fn load(d: &mut Debugger, args: &[&str]) {
+    match args.get(1) {
+        Some(x) => {
+            if let Some(sav) = d.saved(x).cloned() {
+                d.vm.restore(&sav);
+                d.checkpoints.clear();
+                d.set_undo.clear();
+            } else {
+                println!("Unknown state: {}", x);
+            }
+        }
+        _ => {
+            println!("usage: load <step|name>");
+        }
+    }
+}
+
+pub fn name(d: &mut Debugger, args: &[&str]) {
+    match args.get(1) {
+        None => {
+            for (name, snapshot) in d.names.iter().sorted_by_key(|(name, _)| name.as_str()) {
+                println!("  {} (IP {})", name, snapshot.instruction_pointer);
+            }
+        }
+        Some(name) if name.parse::<usize>().is_ok() => println!("A name can't be a number, as it'd read as a step"),
+        Some(name) => {
+            let snapshot = d.vm.snapshot(&mut d.shared_rom);
+            d.names.insert(name.to_string(), snapshot);
+            println!("Named the current state {}", name);
+        }
+    }
+}
+
+/// One `@addr = value` line per address in `start..end`.
+fn memory_lines(vm: &Vm, start: u16, end: u16) -> String {
+    (start..end).map(|a| format!("@{} = {:?}\n", a, vm.try_get(a))).collect()
+}
+
+pub fn get(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).and_then(|w| parse_addr_range(w)) {
+        Some((start, end)) => print!("{}", memory_lines(&d.vm, start, end)),
+        None => println!("usage: get <addr|rN|start..end>"),
+    }
+}
+
+pub fn gets(d: &mut Debugger, args: &[&str]) {
+    let addr: Option<u16> = args.get(1).and_then(|w| w.parse().ok());
+    let len: Option<u16> = args.get(2).and_then(|w| w.parse().ok());
+    match (addr, len, args.len()) {
+        (Some(addr), Some(len), 3) => println!("@{} = {:?}", addr, d.vm.read_mem_string(addr, len)),
+        (Some(addr), None, 2) => {
+            // No length given, so treat it as a length-prefixed string like the game's own.
+            let len = d.vm.try_get(addr).unwrap_or(0);
+            match addr.checked_add(1) {
+                Some(start) => println!("@{} = {:?}", addr, d.vm.read_mem_string(start, len)),
+                None => println!("@{} is out of range for a length-prefixed string", addr),
+            }
+        }
+        _ => println!("usage: gets <addr> [len]"),
+    }
+}
+
+pub fn findseq(d: &mut Debugger, args: &[&str]) {
+    let pattern: Result<Vec<u16>, _> = args.iter().skip(1).map(|w| w.parse()).collect();
+    match pattern {
+        Ok(pattern) if !pattern.is_empty() => {
+            let found = d.vm.find_sequence(&pattern);
+            println!("{} matches: {:?}", found.len(), found);
+        }
+        _ => println!("usage: findseq <v0> <v1> ..."),
+    }
+}
+
+pub fn set(d: &mut Debugger, args: &[&str]) {
+    let a = args.get(1).and_then(|w| parse_addr(w));
+    let b = args.get(2).and_then(|w| w.parse::<u16>().ok());
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let old = d.vm.try_get(a);
+            d.vm.set(a, b);
+            // So that stepping back to after the poke replays with it in place.
+            push_checkpoint(&mut d.checkpoints, &d.vm);
+            println!("@{} = {:?} ==> {}", a, old, b);
+            if d.set_undo.len() == MAX_SET_UNDO {
+                d.set_undo.pop_front();
+            }
+            d.set_undo.push_back((a, old));
+        }
+        _ => {
+            println!("usage: set <loc|r0..r7> <value>");
+        }
+    }
+}
+
+pub fn find_ack(d: &mut Debugger, _args: &[&str]) {
+    let found = teleporter::find_ack_routines(&d.vm);
+    if found.is_empty() {
+        println!("No self-recursive routines found.");
+    }
+    for c in found {
+        println!("@{} ({} confidence): {} self-calls, {}", c.entry, c.confidence(), c.self_calls,
+                 c.features.join(", "));
+    }
+}
+
+pub fn undo_set(d: &mut Debugger, _args: &[&str]) {
+    match d.set_undo.pop_back() {
+        Some((a, old)) => {
+            match old {
+                Some(v) => d.vm.set(a, v),
+                None => d.vm.unset(a),
+            }
+            println!("@{} = {:?} (restored)", a, d.vm.try_get(a));
+        }
+        None => println!("No manual sets to undo."),
+    }
+}
+
+pub fn save(d: &mut Debugger, args: &[&str]) {
+    match args.get(1) {
+        Some(path) => match d.vm.save_to_file(path) {
+            Ok(()) => println!("Saved the VM to {}", path),
+            Err(e) => println!("Couldn't save the VM: {}", e),
+        },
+        None => println!("usage: save <path>"),
+    }
+}
+
+pub fn restore(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|path| (path, Vm::load_from_file(path))) {
+        Some((path, Ok(vm))) => {
+            d.vm = vm;
+            d.vm.set_headless(true);
+            d.checkpoints.clear();
+            d.set_undo.clear();
+            d.step_no += 1;
+            println!("Restored the VM from {} at IP {}", path, d.vm.instruction_pointer);
+        }
+        Some((_, Err(e))) => println!("Couldn't restore the VM: {}", e),
+        None => println!("usage: restore <path>"),
+    }
+}
+
+pub fn session(d: &mut Debugger, args: &[&str]) {
+    match (args.get(1), args.get(2)) {
+        (Some(&"save"), Some(path)) => {
+            let session = session::Session {
+                version: session::SESSION_VERSION,
+                step_no: d.step_no,
+                vm: d.vm.clone(),
+                by_step: d.by_step.clone(),
+                names: d.names.clone(),
+                all_input: d.all_input.clone(),
+            };
+            match session.save(path) {
+                Ok(()) => println!("Saved session to {}", path),
+                Err(e) => println!("Couldn't save session: {}", e),
+            }
+        }
+        (Some(&"load"), Some(path)) => {
+            match session::Session::load(path) {
+                Ok(loaded) => {
+                    d.step_no = loaded.step_no;
+                    d.vm = loaded.vm;
+                    d.vm.set_headless(true);
+                    d.by_step = loaded.by_step;
+                    d.names = loaded.names;
+                    d.all_input = loaded.all_input;
+                    for snapshot in d.by_step.values_mut().chain(d.names.values_mut()) {
+                        snapshot.share_rom(&mut d.shared_rom);
+                    }
+                    d.saves = d.by_step.iter().map(|(&k, v)| (v.clone(), k)).collect();
+                    d.checkpoints.clear();
+                    d.set_undo.clear();
+                    println!("Loaded session from {} at step {}", path, d.step_no);
+                }
+                Err(e) => println!("Couldn't load session: {}", e),
+            }
+        }
+        _ => println!("usage: session save|load <path>"),
+    }
+}
+
+pub fn unmapped(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("error") => d.vm.set_unmapped_read_mode(UnmappedReads::Error),
+        Some("zero") => d.vm.set_unmapped_read_mode(UnmappedReads::ZeroWithWarning),
+        _ => println!("usage: unmapped error|zero"),
+    }
+}
+
+pub fn flush(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("line") => d.vm.set_flush_policy(FlushPolicy::EveryNewline),
+        Some("never") => d.vm.set_flush_policy(FlushPolicy::Never),
+        Some(n) => match n.parse() {
+            Ok(n) if n > 0 => d.vm.set_flush_policy(FlushPolicy::EveryChars(n)),
+            _ => println!("usage: flush line|never|<chars>"),
+        },
+        None => println!("usage: flush line|never|<chars>"),
+    }
+}
+
+pub fn log(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("off") => {
+            let _ = d.vm.set_log(None);
+        }
+        Some(path) => match d.vm.set_log(Some(path)) {
+            Ok(()) => println!("Logging output to {}", path),
+            Err(e) => println!("Couldn't open {}: {}", path, e),
+        },
+        None => println!("usage: log <path>|off"),
+    }
+}
+
+pub fn trace(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.vm.set_trace(true),
+        Some("off") => d.vm.set_trace(false),
+        Some(path) => match d.vm.set_trace_file(path) {
+            Ok(()) => println!("Tracing to {}", path),
+            Err(e) => println!("Couldn't open {}: {}", path, e),
+        },
+        None => println!("usage: trace on|off|<path>"),
+    }
+}
+
+pub fn writes(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.vm.set_track_writes(true),
+        Some("off") => d.vm.set_track_writes(false),
+        Some(_) => println!("usage: writes [on|off]"),
+        None if !d.vm.track_writes => println!("Write tracking is off; turn it on with 'writes on'."),
+        None => {
+            println!("{} addresses written in the last run:", d.vm.writes().len());
+            for (a, change) in d.vm.writes() {
+                println!("  @{} = {:?} ({:?})", a, d.vm.try_get(*a), change);
+            }
+        }
+    }
+}
+
+pub fn stats(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("reset") => d.vm.reset_op_counts(),
+        Some(_) => println!("usage: stats [reset]"),
+        None => {
+            for (op, n) in d.vm.op_counts() {
+                println!("  {:6} {:>12}", format!("{:?}", op), n);
+            }
+            println!("  {:6} {:>12}", "total", d.vm.instruction_count());
+        }
+    }
+}
+
+pub fn profile(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.vm.set_profiling(true),
+        Some("off") => d.vm.set_profiling(false),
+        _ => println!("usage: profile on|off"),
+    }
+}
+
+pub fn echo(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.vm.set_live_output(true),
+        Some("off") => d.vm.set_live_output(false),
+        _ => println!("usage: echo on|off"),
+    }
+}
+
+pub fn selfmod(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.vm.set_self_modifying_warnings(true),
+        Some("off") => d.vm.set_self_modifying_warnings(false),
+        _ => println!("usage: selfmod on|off"),
+    }
+}
+
+pub fn hotspots(d: &mut Debugger, args: &[&str]) {
+    let n = match args.get(1).map(|w| w.parse::<usize>()) {
+        None => 10,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("usage: hotspots [n]");
+            return;
+        }
+    };
+    let hot = d.vm.hotspots(n);
+    if hot.is_empty() {
+        println!("Nothing profiled; turn it on with 'profile on'.");
+    }
+    for (addr, count) in hot {
+        let line = d.vm.disassemble_one_with(addr, &d.disasm_options).map(|(line, _)| line).unwrap_or_default();
+        println!("{:>12}  {}", count, line);
+    }
+}
+
+pub fn checksum(d: &mut Debugger, _args: &[&str]) {
+    println!("{:08x}", d.vm.program_checksum());
+}
+
+pub fn acktable(_d: &mut Debugger, args: &[&str]) {
+    match (args.get(1).map(|w| w.parse::<u16>()), args.get(2).map(|w| w.parse::<u16>())) {
+        (Some(Ok(start)), Some(Ok(end))) if start < end && end <= 32768 => {
+            for (c, result) in ack::ack_table(start..end) {
+                println!("{:5} {:5}", c, result);
+            }
+        }
+        _ => println!("usage: acktable <start> <end> (end exclusive, at most 32768)"),
+    }
+}
+
+pub fn input(d: &mut Debugger, _args: &[&str]) {
+    println!("{}", d.all_input);
+}
+
+pub fn solve(d: &mut Debugger, _args: &[&str]) {
+    d.vm.push_input(PARTIAL_SOLUTION);
+    push_checkpoint(&mut d.checkpoints, &d.vm);
+    let status = d.vm.run_to_input(d.running.clone());
+    if status != Ok(RunStatus::WaitingForInput) {
+        println!("{}", describe(status));
+    }
+    d.step_no += 1;
+}
+
+pub fn solve_coins(_d: &mut Debugger, _args: &[&str]) {
+    match coins::solve() {
+        Some(order) => {
+            for coin in order {
+                println!("use {}", coin);
+            }
+        }
+        None => println!("No order of the coins balances the equation"),
+    }
+}
+
+pub fn try_command(d: &mut Debugger, args: &[&str]) {
+    let mut dry_run = d.vm.clone();
+    dry_run.live_output = false;
+    let _ = dry_run.set_log(None);
+    dry_run.set_trace(false);
+    dry_run.push_input(&args[1..].join(" "));
+    dry_run.push_input("\n");
+    let status = dry_run.run_to_input(d.running.clone());
+    println!("--- dry run, state not advanced ---");
+    print!("{}", dry_run.take_output());
+    if status != Ok(RunStatus::WaitingForInput) {
+        println!("{}", describe(status));
+    }
+    println!("--- end of dry run ---");
+}
+
+pub fn run_to_halt(d: &mut Debugger, _args: &[&str]) {
+    push_checkpoint(&mut d.checkpoints, &d.vm);
+    let status = d.vm.run_through_breakpoints(d.running.clone());
+    println!("{}", describe(status));
+    d.step_no += 1;
+}
+
+fn describe(status: Result<RunStatus, StepError>) -> String {
+    match status {
+        Ok(RunStatus::Halted) => "Halted.".to_string(),
+        Ok(RunStatus::WaitingForInput) => "Stopped: awaiting input.".to_string(),
+        Ok(RunStatus::Interrupted) => "Interrupted.".to_string(),
+        Ok(RunStatus::Breakpoint(at)) => format!("Stopped: hit breakpoint at {}.", at),
+        Ok(RunStatus::Watchpoint { addr, old, new, ip }) => {
+            format!("Stopped: @{} changed from {:?} to {} at ip {}.", addr, old, new, ip)
+        }
+        Err(e) => format!("Stopped by a VM error: {}.", e),
+    }
+}
+
+pub fn breakpoint(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|w| w.parse::<u16>()) {
+        Some(Ok(addr)) => d.vm.add_breakpoint(addr),
+        None => println!("Breakpoints: {:?}", d.vm.breakpoints()),
+        Some(Err(_)) => println!("usage: break [addr]"),
+    }
+}
+
+pub fn watch(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|w| parse_addr(w)) {
+        Some(Some(addr)) => d.vm.add_watch(addr),
+        None => println!("Watching: {:?}", d.vm.watches()),
+        Some(None) => println!("usage: watch [addr|rN]"),
+    }
+}
+
+pub fn unwatch(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).and_then(|w| parse_addr(w)) {
+        Some(addr) => if !d.vm.remove_watch(addr) {
+            println!("Not watching {}", addr);
+        },
+        None => println!("usage: unwatch <addr|rN>"),
+    }
+}
+
+pub fn unbreak(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|w| w.parse::<u16>()) {
+        Some(Ok(addr)) => if !d.vm.remove_breakpoint(addr) {
+            println!("No breakpoint at {}", addr);
+        },
+        _ => println!("usage: unbreak <addr>"),
+    }
+}
+
+pub fn bisect(d: &mut Debugger, args: &[&str]) {
+    let (path_a, path_b) = match args {
+        [_, a, b] => (a, b),
+        _ => {
+            println!("usage: bisect <script-a> <script-b>");
+            return;
+        }
+    };
+    let scripts = std::fs::read_to_string(path_a).and_then(|a| Ok((a, std::fs::read_to_string(path_b)?)));
+    let (a, b) = match scripts {
+        Ok(scripts) => scripts,
+        Err(e) => {
+            println!("Couldn't read scripts: {}", e);
+            return;
+        }
+    };
+    let mut boot = d.vm.clone();
+    boot.rom = d.original_rom.clone();
+    boot.reset();
+    match bisect::first_divergence(&boot, &a, &b) {
+        Ok(None) => println!("The scripts give the same output throughout."),
+        Ok(Some(div)) => {
+            println!("Output differs after line {}: {:?} vs {:?}", div.line + 1, div.commands.0, div.commands.1);
+            println!("--- {} ---\n{}--- {} ---\n{}", path_a, div.outputs.0, path_b, div.outputs.1);
+            println!("IP {} vs {}, stack {:?} vs {:?}", div.states.0.instruction_pointer, div.states.1.instruction_pointer,
+                     div.states.0.stack, div.states.1.stack);
+            for (a, change) in div.memory {
+                println!("  @{} = {:?} vs {:?} ({:?})", a, div.states.0.try_get(a), div.states.1.try_get(a), change);
+            }
+        }
+        Err(e) => println!("Couldn't bisect: {}", e),
+    }
+}
+
+pub fn step(d: &mut Debugger, args: &[&str]) {
+    let n: usize = match args.get(1).map(|w| w.parse()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("usage: step [n]");
+            return;
+        }
+    };
+    push_checkpoint(&mut d.checkpoints, &d.vm);
+    for i in 0..n {
+        if !d.vm.running {
+            println!("Halted after {} steps.", i);
+            break;
+        }
+        if d.vm.needs_input() {
+            println!("Stopped after {} steps: awaiting input.", i);
+            break;
+        }
+        if let Some((line, _)) = d.vm.disassemble_one_with(d.vm.instruction_pointer, &d.disasm_options) {
+            println!("{}", line);
+        }
+        if let Err(e) = d.vm.step() {
+            println!("Stopped after {} steps: {}", i, e);
+            break;
+        }
+    }
+    d.step_no += 1;
+}
+
+pub fn record(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|w| w.parse::<usize>()) {
+        Some(Ok(depth)) => d.vm.set_history_depth(depth),
+        _ => println!("usage: record <depth> (0 to stop)"),
+    }
+}
+
+pub fn undo(d: &mut Debugger, _args: &[&str]) {
+    if d.vm.undo() {
+        println!("Undid a step, back to instruction {} at IP {}", d.vm.instruction_count(), d.vm.instruction_pointer);
+    } else {
+        println!("No recorded steps to undo; start recording with 'record <depth>'.");
+    }
+}
+
+pub fn next(d: &mut Debugger, _args: &[&str]) {
+    push_checkpoint(&mut d.checkpoints, &d.vm);
+    match d.vm.step_over(d.running.clone(), STEP_OVER_BUDGET) {
+        Ok(StepOverStatus::Done) => (),
+        Ok(StepOverStatus::Halted) => println!("Halted."),
+        Ok(StepOverStatus::WaitingForInput) => println!("Stopped: program needs input."),
+        Ok(StepOverStatus::Interrupted) => println!("Interrupted."),
+        Ok(StepOverStatus::OutOfBudget) => println!("The call hadn't returned after {} instructions.", STEP_OVER_BUDGET),
+        Err(e) => println!("Stopped by a VM error: {}.", e),
+    }
+    d.step_no += 1;
+    if let Some((line, _)) = d.vm.disassemble_one_with(d.vm.instruction_pointer, &d.disasm_options) {
+        println!("{}", line);
+    }
+}
+
+pub fn script(d: &mut Debugger, args: &[&str]) {
+    let path = match args {
+        [_, path] => path,
+        _ => {
+            println!("usage: script <path>");
+            return;
+        }
+    };
+    match std::fs::read_to_string(path) {
+        Ok(script) => {
+            let lines = script.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+            for line in lines {
+                if d.done {
+                    break;
+                }
+                d.record_step();
+                println!("> {}", line);
+                d.handle_line(&format!("{}\n", line));
+            }
+        }
+        Err(e) => println!("Couldn't read script: {}", e),
+    }
+}
+
+pub fn replay(d: &mut Debugger, args: &[&str]) {
+    match std::fs::read_to_string(args[1..].join(" ")) {
+        Ok(script) => {
+            d.restart();
+            let mut status = d.vm.run_to_input(d.running.clone());
+            let mut fed = 0;
+            for line in script.lines() {
+                if status != Ok(RunStatus::WaitingForInput) {
+                    break;
+                }
+                d.vm.push_input(line);
+                d.vm.push_input("\n");
+                status = d.vm.run_to_input(d.running.clone());
+                fed += 1;
+            }
+            d.all_input = script.lines().take(fed).map(|l| format!("{}\n", l)).collect();
+            d.step_no += 1;
+            println!("Replayed {} of {} lines, then {} IP {} after {} instructions",
+                     fed, script.lines().count(), describe(status), d.vm.instruction_pointer, d.vm.instruction_count());
+        }
+        Err(e) => println!("Couldn't read script: {}", e),
+    }
+}
+
+pub fn reset(d: &mut Debugger, _args: &[&str]) {
+    d.restart();
+    let status = d.vm.run_to_input(d.running.clone());
+    if status != Ok(RunStatus::WaitingForInput) {
+        println!("{}", describe(status));
+    }
+    d.all_input.clear();
+    d.step_no += 1;
+}
+
+pub fn step_back(d: &mut Debugger, args: &[&str]) {
+    let current = d.vm.instruction_count();
+    let n: u64 = match args.get(1).copied().map(|w| w.parse()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("usage: step-back [n]");
+            return;
+        }
+    };
+    let target = current.saturating_sub(n);
+    match d.checkpoints.iter().rev().find(|c| c.instruction_count() <= target) {
+        None => println!("No snapshot at or before instruction {} to replay from.", target),
+        Some(c) if target - c.instruction_count() > MAX_REPLAY => {
+            println!("Refusing to replay {} instructions (limit {}).", target - c.instruction_count(), MAX_REPLAY)
+        }
+        Some(c) => {
+            let distance = target - c.instruction_count();
+            if distance > SLOW_REPLAY {
+                println!("Replaying {} instructions, this may take a while...", distance);
+            }
+            let mut replay = c.clone();
+            match replay.run_to_count(target) {
+                Ok(()) if replay.instruction_count() == target => {
+                    // Only the machine's state comes from the replay; breakpoints, watches, labels
+                    // and settings stay as they are now.
+                    let snapshot = replay.snapshot(&mut d.shared_rom);
+                    d.vm.restore(&snapshot);
+                    d.vm.instruction_count = target;
+                    println!("Stepped back to instruction {}, IP {}", target, d.vm.instruction_pointer);
+                }
+                Ok(()) => println!("Replay stopped at instruction {} short of {}, state unchanged.",
+                                   replay.instruction_count(), target),
+                Err(e) => println!("Replay failed, state unchanged: {}", e),
+            }
+        }
+    }
+}
+
+pub fn xref(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).and_then(|w| w.parse::<u16>().ok()) {
+        Some(target) => {
+            let sources = d.vm.xrefs().remove(&target).unwrap_or_default();
+            println!("{} calls and jumps to @{}:", sources.len(), target);
+            for addr in sources {
+                if let Some((line, _)) = d.vm.disassemble_one(addr) {
+                    println!("  {}", line);
+                }
+            }
+            let dynamic = d.vm.dynamic_jumps().len();
+            if dynamic > 0 {
+                println!("({} more go through a register, so aren't included)", dynamic);
+            }
+        }
+        None => println!("usage: xref <addr>"),
+    }
+}
+
+pub fn reachable(d: &mut Debugger, _args: &[&str]) {
+    let reachable = d.vm.reachable_code();
+    let ranges = address_ranges(reachable.iter().cloned());
+    println!("{} addresses reachable from 0, in {} ranges:", reachable.len(), ranges.len());
+    for (start, end) in ranges {
+        println!("  {}..={}", start, end);
+    }
+}
+
+pub fn coverage(d: &mut Debugger, _args: &[&str]) {
+    let len = d.vm.program_len();
+    let executed = d.vm.executed_addresses();
+    let percent = if len == 0 { 0.0 } else { 100.0 * executed.len() as f64 / f64::from(len) };
+    println!("{} of {} addresses executed ({:.1}%)", executed.len(), len, percent);
+    println!("Executed:");
+    for (start, end) in address_ranges(executed.iter().cloned()) {
+        println!("  {}..={}", start, end);
+    }
+    println!("Never executed:");
+    for (start, end) in address_ranges((0..len).filter(|&a| !d.vm.was_executed(a))) {
+        println!("  {}..={}", start, end);
+    }
+}
+
+pub fn asm(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).map(|w| w.parse::<u16>()) {
+        Some(Ok(addr)) => {
+            println!("Enter assembly, finishing with a blank line or 'end':");
+            let mut src = String::new();
+            loop {
+                match next_line(&d.lines, &d.quit) {
+                    Some(line) if !line.trim().is_empty() && line.trim() != "end" => src += &line,
+                    _ => break,
+                }
+            }
+            match asm::assemble_at(&src, addr) {
+                Ok(words) => {
+                    for (i, w) in words.iter().enumerate() {
+                        d.vm.set(addr + i as u16, *w);
+                    }
+                    let end = addr + words.len() as u16;
+                    let mut ip = addr;
+                    while ip < end {
+                        match d.vm.disassemble_one(ip) {
+                            Some((line, next)) => {
+                                println!("{}", line);
+                                ip = next;
+                            }
+                            None => break,
+                        }
+                    }
+                }
+                Err(e) => println!("Not patched: {:?}", e),
+            }
+        }
+        _ => println!("usage: asm <addr>"),
+    }
+}
+
+pub fn annotate(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied() {
+        Some("on") => d.disasm_options.annotate_memory = true,
+        Some("off") => d.disasm_options.annotate_memory = false,
+        _ => println!("usage: annotate on|off (comments Rmem/Wmem with what they'd access now)"),
+    }
+}
+
+pub fn dis_save(d: &mut Debugger, args: &[&str]) {
+    let range = match (args.get(2).map(|w| w.parse::<u16>()), args.get(3).map(|w| w.parse::<u16>())) {
+        (None, None) => Some((0, u16::MAX)),
+        (Some(Ok(from)), Some(Ok(to))) => Some((from, to)),
+        _ => None,
+    };
+    match (args.get(1), range) {
+        (Some(path), Some((from, to))) => {
+            let written = File::create(path).and_then(|f| {
+                let mut out = io::BufWriter::new(f);
+                d.vm.write_disassembly(&mut out, from, to, &d.disasm_options)?;
+                out.flush()
+            });
+            match written {
+                Ok(()) => println!("Wrote disassembly of {}..{} to {}", from, to, path),
+                Err(e) => println!("Couldn't write {}: {}", path, e),
+            }
+        }
+        _ => println!("usage: dis-save <path> [<from> <to>]"),
+    }
+}
+
+pub fn view(d: &mut Debugger, args: &[&str]) {
+    match args.get(1).copied().map(|w| w.parse::<u16>()) {
+        None => {
+            d.view_at = Some(d.vm.instruction_pointer);
+            d.view_stack.clear();
+        }
+        Some(Ok(addr)) => {
+            d.view_at = Some(addr);
+            d.view_stack.clear();
+        }
+        Some(Err(_)) => println!("usage: view [addr]"),
+    }
+    if let Some(at) = d.view_at {
+        print_listing(&d.vm, at, &d.disasm_options);
+    }
+}
+
+pub fn follow(d: &mut Debugger, args: &[&str]) {
+    let index = match (args.get(1).copied(), d.view_at) {
+        (Some(w), _) => w.parse::<u16>().ok(),
+        (None, Some(at)) => match d.vm.try_get(at).map(DecodedOp::from) {
+            Some(DecodedOp::Known(op)) => Vm::jump_operand(op),
+            _ => None,
+        },
+        (None, None) => None,
+    };
+    match (d.view_at, index) {
+        (None, _) => println!("Nothing to follow from; use 'view [addr]' first."),
+        (_, None) => println!("usage: follow [operand-index] (the index is needed unless @ is a jump or call)"),
+        (Some(at), Some(i)) => match d.vm.operand(at, i) {
+            None => println!("@{} has no operand {}", at, i),
+            Some(target) => {
+                // A register operand can only be followed to where it points right now.
+                let target = if target >= 32768 { d.vm.try_get(target).unwrap_or(target) } else { target };
+                d.view_stack.push(at);
+                d.view_at = Some(target);
+                print_listing(&d.vm, target, &d.disasm_options);
+            }
+        },
+    }
+}
+
+pub fn back(d: &mut Debugger, _args: &[&str]) {
+    match d.view_stack.pop() {
+        Some(at) => {
+            d.view_at = Some(at);
+            print_listing(&d.vm, at, &d.disasm_options);
+        }
+        None => println!("Nothing to go back to."),
+    }
+}
+
+pub fn label(d: &mut Debugger, args: &[&str]) {
+    match (args.get(1).and_then(|w| w.parse::<u16>().ok()), args.get(2), args.len()) {
+        (_, _, 1) => {
+            for (addr, name) in d.vm.labels() {
+                println!("  {:5} {}", addr, name);
+            }
+        }
+        (Some(addr), Some(name), 3) => d.vm.set_label(addr, name),
+        _ => println!("usage: label [<addr> <name>]"),
+    }
+}
+
+pub fn disasm(d: &mut Debugger, args: &[&str]) {
+    match (args.get(1).map(|w| parse_addr(w)), args.get(2).map(|w| parse_addr(w))) {
+        (None, None) => println!("{}", d.vm.disassemble(&d.disasm_options)),
+        (Some(Some(start)), Some(Some(end))) => print!("{}", d.vm.disassemble_range(start, end, &d.disasm_options)),
+        _ => println!("usage: disasm [<start> <end>]"),
+    }
+}
+
+pub fn backtrace(d: &mut Debugger, _args: &[&str]) {
+    println!("IP {}", d.vm.instruction_pointer);
+    for entry in d.vm.backtrace() {
+        match entry {
+            StackEntry::Return { addr, call_site, callee } => {
+                let callee = match d.vm.labels().get(&callee) {
+                    Some(label) => label.clone(),
+                    None if callee >= 32768 => format!("{} (r{}, now {:?})", callee, callee - 32768, d.vm.try_get(callee)),
+                    None => callee.to_string(),
+                };
+                println!("  returns to {:5}  from call at {} to {}", addr, call_site, callee);
+            }
+            StackEntry::Data(value) => println!("  data {:5}", value),
+        }
+    }
+}
+
+pub fn dump(d: &mut Debugger, _args: &[&str]) {
+    d.vm.dump();
+}
+
+pub fn regs(d: &mut Debugger, _args: &[&str]) {
+    println!("{}", d.vm.registers().iter().enumerate().map(|(r, value)| format!("r{}={}", r, value)).join(" "));
+}
+
+pub fn patch_tele(d: &mut Debugger, _args: &[&str]) {
+    d.vm.patch_teleporter(25734);
+}
+
+pub fn teleport(d: &mut Debugger, _args: &[&str]) {
+    println!("Finding the r7 that makes the confirmation return 6 (Ctrl-C to stop)...");
+    let started = Instant::now();
+    d.running.store(true, Ordering::SeqCst);
+    let found = ack::find_teleporter_setting_while(&d.running);
+    let interrupted = !d.running.swap(false, Ordering::SeqCst);
+    let r7 = match found {
+        Some(r7) => r7,
+        None if interrupted => {
+            println!("Interrupted after {:.1?}", started.elapsed());
+            return;
+        }
+        None => {
+            println!("No r7 makes the confirmation return 6");
+            return;
+        }
+    };
+    println!("r7 = {} (found in {:.1?})", r7, started.elapsed());
+    d.vm.patch_teleporter(r7);
+    d.vm.flash_rom();
+    let before = d.vm.output.len();
+    d.handle_line("use teleporter\n");
+    for code in find_codes(&d.vm.output[before..]) {
+        println!("Code: {}", code);
+    }
+}
+
+pub fn help(_d: &mut Debugger, _args: &[&str]) {
+    print!("{}", commands::help_text());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Reads a character into r0, forever.
+    const ECHO: [u16; 4] = [20, 32768, 6, 0];
+
+    fn debugger(program: &[u16]) -> (Debugger<'_>, mpsc::Sender<String>) {
+        let (tx, rx) = mpsc::channel();
+        let mut vm = Vm::new(program);
+        vm.live_output = false;
+        let running = Arc::new(AtomicBool::new(true));
+        vm.run_to_input(running.clone()).unwrap();
+        (Debugger::new(vm, VecDeque::new(), running, Arc::new(AtomicBool::new(false)), rx), tx)
+    }
+
+    #[test]
+    fn quit_leaves_the_prompt() {
+        let (mut d, tx) = debugger(&ECHO);
+        tx.send("get 0\n".to_string()).unwrap();
+        tx.send("quit\n".to_string()).unwrap();
+        d.run();
+        assert!(d.done);
+        assert_eq!(d.all_input, "get 0\nquit\n");
+    }
+
+    #[test]
+    fn commands_are_matched_by_whole_first_word() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.handle_line("set r1 7\n");
+        assert_eq!(d.vm.try_get(32769), Some(7));
+        assert_eq!(d.step_no, 0);
+        // Not a command, so it goes to the game rather than being taken as `set`.
+        d.handle_line("settings r1 9\n");
+        assert_eq!(d.vm.try_get(32769), Some(7));
+        assert_eq!(d.step_no, 1);
+        d.handle_line("undo-set\n");
+        assert_eq!(d.vm.try_get(32769), Some(0));
+        d.handle_line("quitting\n");
+        assert!(!d.done);
+    }
+
+    #[test]
+    fn echo_off_still_keeps_output() {
+        // out 'A'; in r0; jmp 0
+        let program = [19, 65, 20, 32768, 6, 0];
+        let (mut d, _tx) = debugger(&program);
+        d.handle_line("echo on\n");
+        assert!(d.vm.live_output);
+        d.handle_line("echo off\n");
+        assert!(!d.vm.live_output);
+        d.handle_line("x\n");
+        // One A at boot, and one more for each of the two chars of input.
+        assert_eq!(d.vm.take_output(), "AAA");
+    }
+
+    #[test]
+    fn reset_undoes_pokes_and_flashes() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.handle_line("set 1 32769\n");
+        d.handle_line("x\n");
+        d.vm.flash_rom();
+        assert_eq!(d.vm.get_rom(1), Some(32769));
+        d.handle_line("reset\n");
+        assert_eq!(d.vm.get_rom(1), Some(32768));
+        assert_eq!(d.vm.try_get(1), Some(32768));
+        assert_eq!(d.vm.registers(), [0; 8]);
+        assert_eq!(d.vm.instruction_pointer, 0);
+        assert_eq!(d.all_input, "");
+    }
+
+    #[test]
+    fn steps_share_the_rom_and_load_back() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.record_step();
+        d.handle_line("x\n");
+        d.record_step();
+        assert!(Arc::ptr_eq(&d.by_step[&0].rom, &d.by_step[&1].rom));
+        assert_eq!(d.vm.registers()[0], u16::from(b'\n'));
+        d.handle_line("load 0\n");
+        assert_eq!(d.vm.registers()[0], 0);
+        assert_eq!(d.vm.snapshot(&mut d.shared_rom), d.by_step[&0]);
+    }
+
+    #[test]
+    fn prompt_shows_the_next_instruction() {
+        let (mut d, _tx) = debugger(&ECHO);
+        assert_eq!(d.prompt(0), "STEP 0 (first seen 0) @0 In r0=0: ");
+        d.vm.running = false;
+        assert_eq!(d.prompt(0), "STEP 0 (first seen 0): ");
+    }
+
+    #[test]
+    fn names_work_in_place_of_step_numbers() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.handle_line("name start\n");
+        d.handle_line("name 3\n");
+        assert_eq!(d.names.keys().collect_vec(), vec!["start"]);
+        d.handle_line("x\n");
+        assert_eq!(d.vm.registers()[0], u16::from(b'\n'));
+        d.record_step();
+        assert_eq!(d.saved("1"), d.by_step.get(&1));
+        assert_ne!(d.saved("start"), d.saved("1"));
+        d.handle_line("load start\n");
+        assert_eq!(d.vm.registers()[0], 0);
+        assert_eq!(d.vm.instruction_pointer, 0);
+    }
+
+    #[test]
+    fn pasted_lines_run_in_order() {
+        // in r0; add r2 r1 100; wmem r2 r0; add r1 r1 1; jmp 0
+        let program = [20, 32768, 9, 32770, 32769, 100, 16, 32770, 32768, 9, 32769, 32769, 1, 6, 0];
+        let (mut d, tx) = debugger(&program);
+        // The step finds nothing queued, so waits for the next line rather than taking it early.
+        for line in &["ab\n", "step 3\n", "cd\n", "ef\n", "quit\n"] {
+            tx.send(line.to_string()).unwrap();
+        }
+        d.run();
+        let written: String = (100..109).map(|a| d.vm.try_get(a).unwrap() as u8 as char).collect();
+        assert_eq!(written, "ab\ncd\nef\n");
+        assert_eq!(d.step_no, 4);
+    }
+
+    #[test]
+    fn run_to_halt_runs_through_breakpoints_and_watches() {
+        // add r0 r0 1; noop; halt
+        let program = [9, 32768, 32768, 1, 21, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        let (_tx, rx) = mpsc::channel();
+        let mut d = Debugger::new(vm, VecDeque::new(), Arc::new(AtomicBool::new(true)),
+                                  Arc::new(AtomicBool::new(false)), rx);
+        d.handle_line("break 4\n");
+        d.handle_line("watch r0\n");
+        d.handle_line("run-to-halt\n");
+        assert!(!d.vm.running);
+        assert_eq!(d.vm.registers()[0], 1);
+    }
+
+    #[test]
+    fn step_back_keeps_breakpoints_and_pokes() {
+        // add r0 r0 1; jmp 0
+        let program = [9, 32768, 32768, 1, 6, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        let (_tx, rx) = mpsc::channel();
+        let mut d = Debugger::new(vm, VecDeque::new(), Arc::new(AtomicBool::new(true)),
+                                  Arc::new(AtomicBool::new(false)), rx);
+        d.handle_line("step 4\n");
+        d.handle_line("set r2 7\n");
+        d.handle_line("break 4\n");
+        d.handle_line("step-back 0\n");
+        assert_eq!(d.vm.registers()[2], 7);
+        d.handle_line("step 2\n");
+        d.handle_line("step-back 1\n");
+        assert_eq!(d.vm.instruction_count(), 5);
+        assert_eq!(d.vm.registers()[0], 3);
+        assert_eq!(d.vm.registers()[2], 7);
+        assert!(d.vm.breakpoints.contains(&4));
+    }
+
+    #[test]
+    fn memory_lines_lists_each_address() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.handle_line("set r7 25734\n");
+        assert_eq!(memory_lines(&d.vm, 1, 3), "@1 = Some(32768)\n@2 = Some(6)\n");
+        assert_eq!(memory_lines(&d.vm, 32775, 32776), "@32775 = Some(25734)\n");
+    }
+
+    #[test]
+    fn step_stops_early_at_a_halt() {
+        let program = [21, 21, 21, 0];
+        let (tx, rx) = mpsc::channel();
+        drop(tx);
+        let flag = || Arc::new(AtomicBool::new(false));
+        let mut d = Debugger::new(Vm::new(&program), VecDeque::new(), flag(), flag(), rx);
+        d.vm.live_output = false;
+        d.handle_line("step 2\n");
+        assert_eq!((d.vm.instruction_pointer, d.vm.instruction_count()), (2, 2));
+        d.handle_line("step 5\n");
+        assert_eq!(d.vm.instruction_count(), 4);
+        assert!(!d.vm.running);
+    }
+
+    #[test]
+    fn script_runs_commands_and_game_input() {
+        let (mut d, _tx) = debugger(&ECHO);
+        let path = std::env::temp_dir().join(format!("synacor-script-{}.txt", std::process::id()));
+        std::fs::write(&path, "# set up\nset r1 5\n\n  look\nquit\nset r1 6\n").unwrap();
+        d.handle_line(&format!("script {}\n", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(d.vm.try_get(32769), Some(5));
+        assert_eq!(d.step_no, 1);
+        assert!(d.done);
+    }
+
+    #[test]
+    fn vm_errors_leave_the_debugger_running() {
+        // in r0; mod r0 5 0
+        let program = [20, 32768, 11, 32768, 5, 0];
+        let (mut d, _tx) = debugger(&program);
+        d.handle_line("x\n");
+        assert_eq!(d.step_no, 1);
+        assert!(!d.done);
+        d.handle_line("set r1 3\n");
+        assert_eq!(d.vm.try_get(32769), Some(3));
+    }
+
+    #[test]
+    fn line_endings_dont_reach_the_arguments() {
+        let (mut d, _tx) = debugger(&ECHO);
+        d.handle_line("set r2 12 \r\n");
+        assert_eq!(d.vm.try_get(32770), Some(12));
+        d.handle_line("  quit\r\n");
+        assert!(d.done);
+    }
+}
@@ -0,0 +1,2410 @@
+#![recursion_limit="10000000"]
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::cmp::max;
+use std::fs::File;
+use std::io;
+use std::io::{Read, stdout, Write, stdin};
+use std::convert::TryInto;
+use itertools::Itertools;
+use num_enum::TryFromPrimitive;
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+pub mod vault;
+mod session;
+pub mod asm;
+#[cfg(feature = "solver")]
+pub mod auto;
+#[cfg(feature = "tui")]
+pub mod tui;
+mod teleporter;
+mod coins;
+mod commands;
+mod bisect;
+pub mod debugger;
+pub mod mod_arith;
+#[derive(Debug, TryFromPrimitive, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[repr(u16)]
+pub enum Op {
+    Halt = 0,
+    Set,
+    Push,
+    Pop,
+    Eq,
+    Gt,
+    Jmp,
+    Jt,
+    Jf,
+    Add,
+    Mult,
+    Mod,
+    And,
+    Or,
+    Not,
+    Rmem,
+    Wmem,
+    Call,
+    Ret,
+    Out,
+    In,
+    Nop,
+}
+
+/// A word read as an opcode, where words that aren't opcodes are kept as data rather than errors.
+/// Execution still uses the strict `Op` conversion, since running an unknown opcode is a fault.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodedOp {
+    Known(Op),
+    Unknown(u16),
+}
+
+impl From<u16> for DecodedOp {
+    fn from(word: u16) -> Self {
+        match word.try_into() {
+            Ok(op) => DecodedOp::Known(op),
+            Err(_) => DecodedOp::Unknown(word),
+        }
+    }
+}
+
+/// One decoded instruction: its address, op and operands as stored, so registers are left as
+/// 32768..32775. `args` is only short of `Vm::arg_count(op)` when the program ends mid-instruction.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Instruction {
+    pub addr: u16,
+    pub op: Op,
+    pub args: Vec<u16>,
+}
+
+/// Optional extras for the disassembler.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DisasmOptions {
+    /// Comment `Rmem`/`Wmem` with the addresses and values they'd use right now.
+    pub annotate_memory: bool,
+}
+
+/// How an address differs between two snapshots, in terms of the memory overlay on top of rom.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum MemoryChange {
+    /// Read through to rom in A, but overlaid in B.
+    Overlaid,
+    /// Overlaid in A, but back to the rom value in B.
+    Reverted,
+    /// Overlaid in both, with different values.
+    Changed,
+}
+
+/// What a read from an address with nothing in memory or rom does.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum UnmappedReads {
+    /// Fail the instruction with `VmError::BadAddress`.
+    #[default]
+    Error,
+    /// Read 0, as if memory were zero-filled, and record the address in `unmapped_reads`.
+    ZeroWithWarning,
+}
+
+/// When live output is flushed to stdout.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum FlushPolicy {
+    /// At the end of every line, so the game's text keeps up with the prompt.
+    #[default]
+    EveryNewline,
+    /// Once this many chars have been printed since the last flush, and at the end of every line.
+    EveryChars(usize),
+    /// Only when stdout decides to, or an `In` reads stdin.
+    Never,
+}
+
+impl FlushPolicy {
+    /// Whether to flush after printing `ch`, with `unflushed` chars (including it) printed since the
+    /// last flush.
+    fn should_flush(self, ch: char, unflushed: usize) -> bool {
+        match self {
+            FlushPolicy::EveryNewline => ch == '\n',
+            FlushPolicy::EveryChars(n) => ch == '\n' || unflushed >= n,
+            FlushPolicy::Never => false,
+        }
+    }
+}
+
+/// A fault raised while executing an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VmError {
+    /// The word at the instruction pointer isn't an opcode.
+    UnknownOpcode(u16),
+    /// `pop` with nothing on the stack.
+    StackUnderflow,
+    /// A read from an address with nothing in memory or rom.
+    BadAddress(u16),
+    /// `out` of a value that isn't a valid char.
+    InvalidChar(u16),
+    /// The instruction pointer reached the registers, usually by running off the end of memory.
+    IpInRegisterSpace,
+    /// `mod` by zero, which the spec leaves undefined.
+    DivisionByZero,
+    /// `in` with nothing queued once input has ended.
+    InputExhausted,
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VmError::UnknownOpcode(word) => write!(f, "{} is not an opcode", word),
+            VmError::StackUnderflow => write!(f, "pop from an empty stack"),
+            VmError::BadAddress(addr) => write!(f, "read from unmapped address {}", addr),
+            VmError::InvalidChar(value) => write!(f, "out of {}, which is not a char", value),
+            VmError::IpInRegisterSpace => write!(f, "instruction pointer ran into the registers"),
+            VmError::DivisionByZero => write!(f, "mod by zero"),
+            VmError::InputExhausted => write!(f, "in after the end of input"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// A `VmError` along with the address of the instruction that raised it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StepError {
+    pub ip: u16,
+    pub error: VmError,
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at @{}", self.error, self.ip)
+    }
+}
+
+impl std::error::Error for StepError {}
+
+/// Why `run_to_input` handed control back.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RunStatus {
+    Halted,
+    WaitingForInput,
+    Interrupted,
+    /// About to execute the instruction at this breakpoint.
+    Breakpoint(u16),
+    /// The instruction at `ip` changed watched address `addr`.
+    Watchpoint { addr: u16, old: Option<u16>, new: u16, ip: u16 },
+}
+
+/// One stack entry, as `backtrace` reads it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StackEntry {
+    /// An address just after a `Call`, so probably pushed by it. `callee` is the call's operand as
+    /// stored, which may be a register.
+    Return { addr: u16, call_site: u16, callee: u16 },
+    /// Anything else, presumably pushed by `Push`.
+    Data(u16),
+}
+
+/// Why `step_over` handed control back.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepOverStatus {
+    /// The call returned, or the instruction wasn't a call and was stepped.
+    Done,
+    Halted,
+    WaitingForInput,
+    Interrupted,
+    /// The call was still running when the instruction budget ran out.
+    OutOfBudget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vm<'a> {
+    rom: Cow<'a, [u16]>,
+    /// Every address from 0 through the registers at 32768..32775: the rom with writes applied, so
+    /// a read is a single index. `None` where nothing is mapped.
+    memory: Vec<Option<u16>>,
+    stack: Vec<u16>,
+    instruction_pointer: u16,
+    running: bool,
+    input: Vec<char>,
+    output: String,
+    live_output: bool,
+    #[serde(default)]
+    unmapped_read_mode: UnmappedReads,
+    #[serde(default)]
+    unmapped_reads: Vec<u16>,
+    #[serde(default)]
+    instruction_count: u64,
+    /// How many times each op has executed, indexed by `Op as usize`.
+    #[serde(default)]
+    op_counts: [u64; 22],
+    /// Executions per instruction address, while profiling is on.
+    #[serde(skip)]
+    profile: Option<HashMap<u16, u64>>,
+    #[serde(default)]
+    track_writes: bool,
+    #[serde(default)]
+    writes: BTreeMap<u16, MemoryChange>,
+    /// Never read stdin: an `In` with nothing queued stops the machine instead, until more input is
+    /// pushed.
+    #[serde(default)]
+    headless: bool,
+    #[serde(default)]
+    flush_policy: FlushPolicy,
+    /// Names for addresses, shown in disassembly.
+    #[serde(default)]
+    labels: BTreeMap<u16, String>,
+    /// `run_to_input` stops before executing an instruction at any of these.
+    #[serde(default)]
+    breakpoints: HashSet<u16>,
+    /// The breakpoint last stopped at, which the next run should execute rather than stop at again
+    /// if it's still there.
+    #[serde(skip)]
+    stopped_at: Option<u16>,
+    /// `run_to_input` stops after any instruction that changes one of these.
+    #[serde(default)]
+    watches: HashSet<u16>,
+    /// The first change to a watched address in the current step, as `(addr, old, new)`.
+    #[serde(skip)]
+    watch_hit: Option<(u16, Option<u16>, u16)>,
+    /// Where output is also written, whether or not it's shown live. Shared between clones.
+    #[serde(skip)]
+    log: Option<Arc<Mutex<io::LineWriter<File>>>>,
+    /// Chars printed live since the last flush.
+    #[serde(skip)]
+    unflushed: usize,
+    /// Where each instruction is written, with its operands resolved, just before it executes.
+    #[serde(skip)]
+    trace: Option<TraceTo>,
+    /// No more input will come: once the queue is empty, `In` fails rather than reading stdin.
+    /// Set when stdin reaches end of file.
+    #[serde(default)]
+    input_closed: bool,
+    /// Stopped by an `In` with nothing queued, in headless mode.
+    #[serde(default)]
+    waiting_for_input: bool,
+    /// Snapshots from before each of the most recent steps, oldest first, while recording.
+    #[serde(skip)]
+    history: VecDeque<Vm<'a>>,
+    #[serde(skip)]
+    history_depth: usize,
+    /// Decoded opcodes by address, filled as instructions are executed and cleared by `set`.
+    #[serde(skip)]
+    decoded: Vec<Option<Op>>,
+    /// A bit per address below 32768, set once it's been executed as an opcode or operand. Saved
+    /// with the machine, so coverage builds up across a session.
+    #[serde(default)]
+    executed: Vec<u64>,
+    /// Print a warning whenever `Wmem` writes to an address in `executed`.
+    #[serde(default)]
+    warn_self_modifying: bool,
+}
+
+/// Where `Vm::step` writes its trace.
+#[derive(Debug, Clone)]
+enum TraceTo {
+    Stdout,
+    File(Arc<Mutex<io::LineWriter<File>>>),
+}
+
+/// The state of a `Vm` at one moment, cheap enough to keep one per debugger step: the rom is shared
+/// with other snapshots taken over it, and memory is kept only where it differs from the rom.
+/// Compares the same way as the `Vm` it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Snapshot {
+    rom: Arc<[u16]>,
+    overlay: BTreeMap<u16, u16>,
+    stack: Vec<u16>,
+    instruction_pointer: u16,
+    running: bool,
+    input: Vec<char>,
+    output: String,
+    live_output: bool,
+    unmapped_read_mode: UnmappedReads,
+    unmapped_reads: Vec<u16>,
+}
+
+impl Snapshot {
+    /// The value at `address` if it differs from the rom, registers included.
+    pub fn overlay_get(&self, address: u16) -> Option<u16> {
+        self.overlay.get(&address).cloned()
+    }
+    /// Every address whose overlay entry differs between `self` and `other`, in address order.
+    pub fn diff_memory(&self, other: &Snapshot) -> Vec<(u16, MemoryChange)> {
+        let keys: BTreeSet<u16> = self.overlay.keys().chain(other.overlay.keys()).cloned().collect();
+        keys.into_iter()
+            .filter_map(|k| match (self.overlay.get(&k), other.overlay.get(&k)) {
+                (None, Some(_)) => Some((k, MemoryChange::Overlaid)),
+                (Some(_), None) => Some((k, MemoryChange::Reverted)),
+                (Some(a), Some(b)) if a != b => Some((k, MemoryChange::Changed)),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Where the two stacks part ways, if they differ: the index of the first entry that isn't
+    /// shared, and the rest of each stack from there, bottom first.
+    pub fn diff_stack<'s>(&'s self, other: &'s Snapshot) -> Option<(usize, &'s [u16], &'s [u16])> {
+        let common = self.stack.iter().zip(&other.stack).take_while(|(a, b)| a == b).count();
+        if common == self.stack.len() && common == other.stack.len() {
+            None
+        } else {
+            Some((common, &self.stack[common..], &other.stack[common..]))
+        }
+    }
+    /// Both instruction pointers, if they differ.
+    pub fn diff_ip(&self, other: &Snapshot) -> Option<(u16, u16)> {
+        Some((self.instruction_pointer, other.instruction_pointer)).filter(|(a, b)| a != b)
+    }
+    /// Points this snapshot's rom at `shared` if they hold the same words, or makes it the one to
+    /// share from now on if not. For snapshots that were loaded, and so each have their own copy.
+    pub fn share_rom(&mut self, shared: &mut Arc<[u16]>) {
+        if self.rom == *shared {
+            self.rom = shared.clone();
+        } else {
+            *shared = self.rom.clone();
+        }
+    }
+}
+
+/// The parts of a `Vm` that `Eq` and `Hash` look at.
+type StateKey<'s> = (&'s [u16], &'s [Option<u16>], &'s [u16], u16, bool, &'s [char], &'s str, bool,
+                     UnmappedReads, &'s [u16]);
+
+impl<'a> PartialEq for Vm<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.state_key() == other.state_key()
+    }
+}
+
+impl<'a> Eq for Vm<'a> {}
+
+impl<'a> Hash for Vm<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.state_key().hash(state)
+    }
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a [u16]) -> Self {
+        Vm::new_with_registers(program, [0; 8])
+    }
+    /// Like `new`, but starting with the given values in r0..r7, so a routine can be run in isolation.
+    pub fn new_with_registers(program: &'a [u16], registers: [u16; 8]) -> Self {
+        Vm::with_rom(Cow::from(program), registers)
+    }
+    fn with_rom(rom: Cow<'a, [u16]>, registers: [u16; 8]) -> Self {
+        let memory = memory_over(&rom, (32768..).zip(registers.iter().cloned()));
+        Vm {
+            rom,
+            memory,
+            stack: Vec::new(),
+            instruction_pointer: 0,
+            running: true,
+            input: Vec::new(),
+            output: String::new(),
+            live_output: true,
+            unmapped_read_mode: UnmappedReads::Error,
+            unmapped_reads: Vec::new(),
+            instruction_count: 0,
+            op_counts: [0; 22],
+            profile: None,
+            track_writes: false,
+            writes: BTreeMap::new(),
+            headless: false,
+            waiting_for_input: false,
+            input_closed: false,
+            flush_policy: FlushPolicy::default(),
+            labels: BTreeMap::new(),
+            breakpoints: HashSet::new(),
+            stopped_at: None,
+            watches: HashSet::new(),
+            watch_hit: None,
+            unflushed: 0,
+            log: None,
+            history: VecDeque::new(),
+            history_depth: 0,
+            decoded: Vec::new(),
+            trace: None,
+            executed: Vec::new(),
+            warn_self_modifying: false,
+        }
+    }
+    /// A machine owning the program read from `path`, as for `load_program`.
+    pub fn from_file(path: &str) -> io::Result<Vm<'static>> {
+        Ok(Vm::with_rom(Cow::Owned(load_program(path)?), [0; 8]))
+    }
+    /// A machine owning the program in `data`, as for `words_from_bytes`.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Vm<'static>> {
+        Ok(Vm::with_rom(Cow::Owned(words_from_bytes(data)?), [0; 8]))
+    }
+    /// Like `new`, but with the program loaded over a full zero-filled 32768-word address space, as
+    /// the spec describes. Reads past the end of the program then give 0 rather than `BadAddress`.
+    pub fn new_zeroed(program: &[u16]) -> Vm<'static> {
+        let mut full = program.to_vec();
+        if full.len() < 32768 {
+            full.resize(32768, 0);
+        }
+        Vm::with_rom(Cow::Owned(full), [0; 8])
+    }
+    /// Writes the whole machine to `path` as JSON. The rom is written out too, so the file stands
+    /// alone.
+    pub fn save_to_file(&self, path: &str) -> io::Result<()> {
+        let writer = io::BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+    /// Reads a machine written by `save_to_file`.
+    pub fn load_from_file(path: &str) -> io::Result<Vm<'static>> {
+        let reader = io::BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+    /// Everything except counters, so that arriving back at a state compares equal to the first visit.
+    fn state_key(&self) -> StateKey<'_> {
+        (&self.rom, &self.memory, &self.stack, self.instruction_pointer, self.running, &self.input, &self.output,
+         self.live_output, self.unmapped_read_mode, &self.unmapped_reads)
+    }
+    /// The state `diff` and `load` need, sharing `shared_rom` if this VM's rom is the same, or
+    /// replacing it with a copy of this one's if not.
+    pub fn snapshot(&self, shared_rom: &mut Arc<[u16]>) -> Snapshot {
+        if **shared_rom != *self.rom {
+            *shared_rom = Arc::from(&*self.rom);
+        }
+        Snapshot {
+            rom: shared_rom.clone(),
+            overlay: self.overlay().collect(),
+            stack: self.stack.clone(),
+            instruction_pointer: self.instruction_pointer,
+            running: self.running,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            live_output: self.live_output,
+            unmapped_read_mode: self.unmapped_read_mode,
+            unmapped_reads: self.unmapped_reads.clone(),
+        }
+    }
+    /// Puts the machine back to `snapshot`. Counters, coverage, labels, breakpoints and settings
+    /// other than `live_output` aren't part of a snapshot, so are kept as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if *self.rom != *snapshot.rom {
+            self.rom = Cow::Owned(snapshot.rom.to_vec());
+        }
+        self.memory = memory_over(&self.rom, snapshot.overlay.iter().map(|(&a, &v)| (a, v)));
+        self.stack = snapshot.stack.clone();
+        self.instruction_pointer = snapshot.instruction_pointer;
+        self.running = snapshot.running;
+        self.input = snapshot.input.clone();
+        self.output = snapshot.output.clone();
+        self.live_output = snapshot.live_output;
+        self.unmapped_read_mode = snapshot.unmapped_read_mode;
+        self.unmapped_reads = snapshot.unmapped_reads.clone();
+        self.decoded.clear();
+        self.history.clear();
+        self.stopped_at = None;
+        self.watch_hit = None;
+        self.waiting_for_input = false;
+    }
+    /// How many instructions have been executed since the VM was created.
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+    /// Bakes memory below 32768 into the rom, with anything unmapped as 0, leaving only the
+    /// registers differing from it.
+    fn flash_rom(&mut self) {
+        let new_rom: Vec<u16> = (0..32768).map(|a| self.try_get(a).unwrap_or(0)).collect();
+        for (a, &word) in new_rom.iter().enumerate() {
+            self.memory[a] = Some(word);
+        }
+        self.rom = Cow::from(new_rom);
+    }
+    fn dump(&mut self) {
+        println!("VM: Stack: {:?}, IP: {}, instructions executed: {}", self.stack, self.instruction_pointer, self.instruction_count);
+    }
+    fn fetch_read(&mut self) -> Result<u16, VmError> {
+        let i = self.fetch_set()?;
+        if i >= 32768 {
+            self.read(i)
+        } else { Ok(i) }
+    }
+    /// Fetches and decodes the opcode at the IP, from the decode cache where possible.
+    fn fetch_op(&mut self) -> Result<Op, VmError> {
+        let ip = usize::from(self.instruction_pointer);
+        if let Some(Some(op)) = self.decoded.get(ip) {
+            let op = *op;
+            self.instruction_pointer += 1;
+            return Ok(op);
+        }
+        let raw = self.fetch_set()?;
+        // An opcode taken from a register depends on the register, so isn't cacheable.
+        let word = if raw >= 32768 { self.read(raw)? } else { raw };
+        let op: Op = word.try_into().map_err(|_| VmError::UnknownOpcode(word))?;
+        if raw < 32768 {
+            if self.decoded.len() <= ip {
+                self.decoded.resize(ip + 1, None);
+            }
+            self.decoded[ip] = Some(op);
+        }
+        Ok(op)
+    }
+    fn fetch_set(&mut self) -> Result<u16, VmError> {
+        let i = self.read(self.instruction_pointer)?;
+        self.instruction_pointer += 1;
+        //self.log(format!("{} ", i));
+        Ok(i)
+    }
+    fn get_rom(&self, addr: u16) -> Option<u16> {
+        let a_us: usize = addr.into();
+        if a_us < self.rom.len() {
+            Some(self.rom[a_us])
+        } else {
+            None
+        }
+    }
+    pub fn set(&mut self, address: u16, value: u16) {
+        if !self.watches.is_empty() && self.watch_hit.is_none() && self.watches.contains(&address) {
+            let old = self.try_get(address);
+            if old != Some(value) {
+                self.watch_hit = Some((address, old, value));
+            }
+        }
+        let i = usize::from(address);
+        if i >= self.memory.len() {
+            self.memory.resize(i + 1, None);
+        }
+        let rom = self.get_rom(address);
+        let change = if rom == Some(value) {
+            MemoryChange::Reverted
+        } else if self.memory[i] != rom {
+            MemoryChange::Changed
+        } else {
+            MemoryChange::Overlaid
+        };
+        self.memory[i] = Some(value);
+        if self.track_writes {
+            self.writes.insert(address, change);
+        }
+        if let Some(cached) = self.decoded.get_mut(usize::from(address)) {
+            *cached = None;
+        }
+    }
+    /// r0..r7 as they are now.
+    pub fn registers(&self) -> [u16; 8] {
+        let mut regs = [0; 8];
+        for (r, value) in regs.iter_mut().enumerate() {
+            *value = self.try_get(32768 + r as u16).unwrap_or(0);
+        }
+        regs
+    }
+    pub fn try_get(&self, address: u16) -> Option<u16> {
+        self.memory.get(usize::from(address)).cloned().flatten()
+    }
+    /// Puts `address` back to what the rom has there, or unmapped past its end.
+    fn unset(&mut self, address: u16) {
+        if let Some(word) = self.memory.get_mut(usize::from(address)) {
+            *word = self.rom.get(usize::from(address)).cloned();
+        }
+        if let Some(cached) = self.decoded.get_mut(usize::from(address)) {
+            *cached = None;
+        }
+    }
+    /// The value at `address` if it's been written to differ from the rom. Registers are always
+    /// counted as written.
+    fn overlay_get(&self, address: u16) -> Option<u16> {
+        self.try_get(address).filter(|&v| self.get_rom(address) != Some(v))
+    }
+    /// Every address whose value differs from the rom, with that value, in address order.
+    fn overlay(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        (0..self.memory.len()).filter_map(move |a| self.overlay_get(a as u16).map(|v| (a as u16, v)))
+    }
+    pub fn get(&self, address: u16) -> Result<u16, VmError> {
+        match self.try_get(address) {
+            Some(v) => Ok(v),
+            None if self.unmapped_read_mode == UnmappedReads::ZeroWithWarning => Ok(0),
+            None => Err(VmError::BadAddress(address)),
+        }
+    }
+    /// `get` on behalf of the running program, which records any unmapped reads.
+    fn read(&mut self, address: u16) -> Result<u16, VmError> {
+        if self.unmapped_read_mode == UnmappedReads::ZeroWithWarning && self.try_get(address).is_none() {
+            self.unmapped_reads.push(address);
+        }
+        self.get(address)
+    }
+    /// In headless mode `In` never falls back to reading stdin, so tests and scripts can't block on it.
+    pub fn set_headless(&mut self, on: bool) {
+        self.headless = on;
+    }
+    /// Whether a `Wmem` to an address that's already been executed prints a warning.
+    pub fn set_self_modifying_warnings(&mut self, on: bool) {
+        self.warn_self_modifying = on;
+    }
+    /// Whether `addr` has been executed, as an opcode or one of its operands, since the VM was
+    /// created or reset.
+    pub fn was_executed(&self, addr: u16) -> bool {
+        self.executed.get(usize::from(addr / 64)).is_some_and(|bits| bits & (1 << (addr % 64)) != 0)
+    }
+    /// Every address that `was_executed`, in order.
+    pub fn executed_addresses(&self) -> Vec<u16> {
+        (0..32768).filter(|&a| self.was_executed(a)).collect()
+    }
+    /// How many words the program has: the rom, or further if the overlay has been written past
+    /// its end.
+    pub fn program_len(&self) -> u16 {
+        let overlay_end = self.overlay().take_while(|&(a, _)| a < 32768).last().map_or(0, |(a, _)| a + 1);
+        (self.rom.len().min(32768) as u16).max(overlay_end)
+    }
+    fn mark_executed(&mut self, ip: u16, len: u16) {
+        if self.executed.is_empty() {
+            self.executed.resize(32768 / 64, 0);
+        }
+        for addr in ip..(ip + len).min(32768) {
+            self.executed[usize::from(addr / 64)] |= 1 << (addr % 64);
+        }
+    }
+    /// Whether output is echoed to stdout as it's printed, rather than only kept for `take_output`.
+    pub fn set_live_output(&mut self, on: bool) {
+        self.live_output = on;
+    }
+    /// Each op that has executed since the last `reset_op_counts`, with its count, most frequent
+    /// first.
+    pub fn op_counts(&self) -> Vec<(Op, u64)> {
+        (0..22_u16)
+            .filter_map(|i| Op::try_from_primitive(i).ok())
+            .map(|op| (op, self.op_counts[op as usize]))
+            .filter(|&(_, n)| n > 0)
+            .sorted_by_key(|&(_, n)| std::cmp::Reverse(n))
+            .collect()
+    }
+    pub fn reset_op_counts(&mut self) {
+        self.op_counts = [0; 22];
+    }
+    /// Starts counting executions per address afresh, or stops and discards the counts.
+    pub fn set_profiling(&mut self, on: bool) {
+        self.profile = if on { Some(HashMap::new()) } else { None };
+    }
+    /// The `n` most executed addresses since profiling was turned on, with their counts.
+    pub fn hotspots(&self, n: usize) -> Vec<(u16, u64)> {
+        self.profile.iter()
+            .flatten()
+            .map(|(&a, &c)| (a, c))
+            .sorted_by_key(|&(a, c)| (std::cmp::Reverse(c), a))
+            .take(n)
+            .collect()
+    }
+    pub fn set_label(&mut self, addr: u16, name: &str) {
+        self.labels.insert(addr, name.to_string());
+    }
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+    /// Starts keeping a snapshot of the machine before each of the last `depth` steps, so that
+    /// `undo` can take them back; 0 stops recording and drops the snapshots.
+    ///
+    /// Each snapshot is a full copy of the memory overlay and stack, a few hundred KB for the
+    /// challenge once it's running, so the history can take up `depth` times that. Copying it on
+    /// every step also makes running far slower, so this is for stepping through code, not playing.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
+    fn record_history(&mut self) {
+        let history = std::mem::take(&mut self.history);
+        let decoded = std::mem::take(&mut self.decoded);
+        let snapshot = self.clone();
+        self.history = history;
+        self.decoded = decoded;
+        if self.history.len() == self.history_depth {
+            self.history.pop_front();
+        }
+        self.history.push_back(snapshot);
+    }
+    /// Goes back to before the last recorded step. False if there's nothing to go back to.
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(mut previous) => {
+                previous.history = std::mem::take(&mut self.history);
+                previous.history_depth = self.history_depth;
+                *self = previous;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+    /// Whether there was a breakpoint at `addr` to remove.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+    pub fn breakpoints(&self) -> Vec<u16> {
+        self.breakpoints.iter().cloned().sorted().collect()
+    }
+    pub fn add_watch(&mut self, addr: u16) {
+        self.watches.insert(addr);
+    }
+    /// Whether `addr` was being watched.
+    pub fn remove_watch(&mut self, addr: u16) -> bool {
+        self.watches.remove(&addr)
+    }
+    pub fn watches(&self) -> Vec<u16> {
+        self.watches.iter().cloned().sorted().collect()
+    }
+    /// Appends all output from now on to the file at `path`, a line at a time, or stops if `None`.
+    pub fn set_log(&mut self, path: Option<&str>) -> io::Result<()> {
+        self.log = match path {
+            Some(path) => {
+                let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                Some(Arc::new(Mutex::new(io::LineWriter::new(file))))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+    /// Prints each instruction as it's executed, or stops if `on` is false.
+    pub fn set_trace(&mut self, on: bool) {
+        self.trace = if on { Some(TraceTo::Stdout) } else { None };
+    }
+    /// Writes each instruction as it's executed to the file at `path`, replacing what was there.
+    pub fn set_trace_file(&mut self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        self.trace = Some(TraceTo::File(Arc::new(Mutex::new(io::LineWriter::new(file)))));
+        Ok(())
+    }
+    /// The instruction at `ip` for the trace: `@ip Op` and its operands, with registers shown as
+    /// `rN=value` so they can be told apart from literals.
+    fn trace_line(&self, ip: u16) -> String {
+        match self.decode(ip) {
+            Some((ins, _)) => self.describe_instruction(&ins),
+            None => format!("@{} {:?}", ip, self.try_get(ip)),
+        }
+    }
+    /// `@addr Op` and the operands of `ins`, with registers shown as `rN=value` as they are now.
+    pub fn describe_instruction(&self, ins: &Instruction) -> String {
+        let mut line = format!("@{} {:?}", ins.addr, ins.op);
+        for &arg in &ins.args {
+            line += &format!(" {}", self.describe_operand(arg));
+        }
+        line
+    }
+    /// Marks the end of input, so that once what's queued runs out `In` fails with
+    /// `VmError::InputExhausted` rather than waiting or reading stdin.
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+    pub fn set_unmapped_read_mode(&mut self, mode: UnmappedReads) {
+        self.unmapped_read_mode = mode;
+    }
+    /// Addresses read while unmapped (in `ZeroWithWarning` mode) since the last call.
+    pub fn take_unmapped_reads(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.unmapped_reads)
+    }
+    /// Puts the VM back as it was when constructed: overlay, registers, stack, input, output and
+    /// counters are cleared. The rom is kept, as are settings like `live_output`.
+    pub fn reset(&mut self) {
+        let rom = std::mem::replace(&mut self.rom, Cow::Borrowed(&[]));
+        let mut fresh = Vm::with_rom(rom, [0; 8]);
+        fresh.live_output = self.live_output;
+        fresh.unmapped_read_mode = self.unmapped_read_mode;
+        fresh.track_writes = self.track_writes;
+        fresh.headless = self.headless;
+        fresh.flush_policy = self.flush_policy;
+        fresh.warn_self_modifying = self.warn_self_modifying;
+        fresh.log = self.log.take();
+        fresh.trace = self.trace.take();
+        fresh.labels = std::mem::take(&mut self.labels);
+        fresh.breakpoints = std::mem::take(&mut self.breakpoints);
+        fresh.watches = std::mem::take(&mut self.watches);
+        *self = fresh;
+    }
+    /// Queues `text` as input, after anything already queued. Each line is normalised first so that
+    /// pasted commands match what the game expects: `\r` is dropped, tabs become spaces, and
+    /// trailing spaces are trimmed.
+    pub fn push_input(&mut self, text: &str) {
+        let normalised: String = text.split_inclusive('\n')
+            .map(|line| {
+                let line = line.replace('\r', "").replace('\t', " ");
+                match line.strip_suffix('\n') {
+                    Some(l) => format!("{}\n", l.trim_end_matches(' ')),
+                    None => line.trim_end_matches(' ').to_string(),
+                }
+            })
+            .collect();
+        self.feed_str(&normalised);
+    }
+    /// Queues `text` as input exactly as given, after anything already queued.
+    pub fn feed_str(&mut self, text: &str) {
+        let mut input: Vec<char> = text.chars().rev().collect();
+        input.append(&mut self.input);
+        self.input = input;
+        if self.waiting_for_input && !self.input.is_empty() {
+            self.waiting_for_input = false;
+            self.running = true;
+        }
+    }
+    /// Replaces whatever input is queued with `text`, exactly as given.
+    pub fn set_input_queue(&mut self, text: &str) {
+        self.input.clear();
+        self.feed_str(text);
+    }
+    /// Turns on recording of the addresses written during each `run_to_input`, which `writes` returns.
+    pub fn set_track_writes(&mut self, on: bool) {
+        self.track_writes = on;
+        self.writes.clear();
+    }
+    /// Addresses written during the last run (if tracking), with how the last write to each one
+    /// left the overlay. Writing a value that was already there still counts.
+    pub fn writes(&self) -> &BTreeMap<u16, MemoryChange> {
+        &self.writes
+    }
+    fn binop<FN>(&mut self, f: FN) -> Result<(), VmError>
+        where FN: Fn(u16, u16) -> u16
+    {
+        let (a, b, c) = (self.fetch_set()?, self.fetch_read()?, self.fetch_read()?);
+        self.set(a, (f(b, c)) % 32768);
+        Ok(())
+    }
+    pub fn arg_count(o: Op) -> u16 {
+        match o {
+            Op::Halt | Op::Ret | Op::Nop => 0,
+            Op::Push | Op::Pop | Op::Jmp | Op::Call | Op::Out | Op::In => 1,
+            Op::Set | Op::Jt | Op::Jf | Op::Not | Op::Rmem |
+            Op::Wmem => 2,
+            Op::Eq | Op::Gt | Op::Add | Op::Mult |
+            Op::Mod | Op::And | Op::Or => 3,
+        }
+    }
+    fn step(&mut self) -> Result<(), StepError> {
+        if self.history_depth > 0 {
+            self.record_history();
+        }
+        if self.headless && self.needs_input() {
+            self.running = false;
+            self.waiting_for_input = true;
+            return Ok(());
+        }
+        let ip = self.instruction_pointer;
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(ip).or_insert(0) += 1;
+        }
+        if let Some(trace) = &self.trace {
+            let line = self.trace_line(ip);
+            match trace {
+                TraceTo::Stdout => println!("{}", line),
+                TraceTo::File(file) => {
+                    let _ = writeln!(file.lock().unwrap(), "{}", line);
+                }
+            }
+        }
+        self.execute().map_err(|error| StepError { ip, error })?;
+        self.instruction_count += 1;
+        Ok(())
+    }
+    fn execute(&mut self) -> Result<(), VmError> {
+        if self.instruction_pointer >= 32768 {
+            return Err(VmError::IpInRegisterSpace);
+        }
+        //self.log(format!("@{} ",self.instruction_pointer));
+        let op = self.fetch_op()?;
+        self.op_counts[op as usize] += 1;
+        self.mark_executed(self.instruction_pointer - 1, 1 + Vm::arg_count(op));
+        match op {
+            Op::Halt => self.running = false,
+            Op::Set => {
+                let a = self.fetch_set()?;
+                let b = self.fetch_read()?;
+                self.set(a, b);
+            }
+            Op::Push => {
+                let a = self.fetch_read()?;
+                self.stack.push(a);
+            }
+            Op::Pop => {
+                let a = self.fetch_set()?;
+                let v = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                self.set(a, v);
+            }
+            Op::Eq => self.binop(|a, b| (a == b).into())?,
+            Op::Gt => self.binop(|a, b| (a > b).into())?,
+            Op::Jmp => self.instruction_pointer = self.fetch_read()?,
+            Op::Jt => {
+                let (a, b) = (self.fetch_read()?, self.fetch_read()?);
+                if a != 0 {
+                    self.instruction_pointer = b;
+                }
+            }
+            Op::Jf => {
+                let (a, b) = (self.fetch_read()?, self.fetch_read()?);
+                if a == 0 {
+                    self.instruction_pointer = b;
+                }
+            }
+            Op::Add => self.binop(|a, b| ((u32::from(a) + u32::from(b)) % 32768) as u16)?,
+            Op::Mult => self.binop(mod_arith::mod_mul_u16)?,
+            Op::Mod => {
+                let (a, b, c) = (self.fetch_set()?, self.fetch_read()?, self.fetch_read()?);
+                let r = b.checked_rem(c).ok_or(VmError::DivisionByZero)?;
+                self.set(a, r);
+            }
+            Op::And => self.binop(|a, b| a & b)?,
+            Op::Or => self.binop(|a, b| a | b)?,
+            Op::Not => {
+                let a = self.fetch_set()?;
+                let b = self.fetch_read()?;
+                self.set(a, (!b) % 32768);
+            }
+            Op::Rmem => {
+                let a = self.fetch_set()?;
+                let ab = self.fetch_read()?;
+                let b = self.read(ab)?;
+                self.set(a, b);
+            }
+            Op::Wmem => {
+                let a = self.fetch_read()?;
+                let b = self.fetch_read()?;
+                if self.warn_self_modifying && self.was_executed(a) {
+                    println!("WARNING: self-modifying write to @{} (previously executed)", a);
+                }
+                self.set(a, b);
+            }
+            Op::Call => {
+                let a = self.fetch_read()?;
+                self.stack.push(self.instruction_pointer);
+                self.instruction_pointer = a;
+            }
+            Op::Ret => {
+                match self.stack.pop() {
+                    None => self.running = false,
+                    Some(a) => self.instruction_pointer = a,
+                }
+            }
+            Op::Out => {
+                let ch: u16 = self.fetch_read()?;
+                let ch: char = std::char::from_u32(ch.into()).ok_or(VmError::InvalidChar(ch))?;
+                self.output.push(ch);
+                if let Some(log) = &self.log {
+                    let _ = write!(log.lock().unwrap(), "{}", ch);
+                }
+                if self.live_output {
+                    print!("{}", ch);
+                    self.unflushed += 1;
+                    if self.flush_policy.should_flush(ch, self.unflushed) {
+                        let _ = stdout().flush();
+                        self.unflushed = 0;
+                    }
+                }
+            }
+            Op::In => {
+                if self.input.is_empty() && !self.input_closed {
+                    let _ = stdout().flush();
+                    self.unflushed = 0;
+                    let mut s = String::new();
+                    match stdin().read_line(&mut s) {
+                        Ok(0) | Err(_) => self.input_closed = true,
+                        Ok(_) => self.push_input(&s),
+                    }
+                }
+                let a = self.fetch_set()?;
+                let i = self.input.pop().ok_or(VmError::InputExhausted)? as u16;
+                self.set(a, i);
+            }
+            Op::Nop => (), // NoOp
+        }
+        Ok(())
+    }
+    pub fn disassemble(&self, opts: &DisasmOptions) -> String {
+        self.disassemble_range(0, u16::MAX, opts)
+    }
+    /// The disassembly of the instructions starting in `start..end`, one per line. Decoding starts at
+    /// `start` even if that's partway through an instruction.
+    pub fn disassemble_range(&self, start: u16, end: u16, opts: &DisasmOptions) -> String {
+        let mut my_ip = start;
+        let mut ans = String::new();
+        while my_ip < end {
+            match self.disassemble_one_with(my_ip, opts) {
+                Some((line, next)) => {
+                    if let Some(label) = self.labels.get(&my_ip) {
+                        ans += &format!("{}:\n", label);
+                    }
+                    ans += &line;
+                    ans += "\n";
+                    my_ip = next;
+                }
+                None => break,
+            }
+        }
+        ans
+    }
+    /// Skips the teleporter's confirmation routine, making it behave as if it returned 6 for `r7`.
+    pub fn patch_teleporter(&mut self, r7: u16) {
+        self.set(5485, 6);
+        self.set(5489, 21);
+        self.set(5490, 21);
+        self.set(32775, r7);
+    }
+    /// Which operand of `op` holds its jump target, for the ops that have one.
+    pub fn jump_operand(op: Op) -> Option<u16> {
+        match op {
+            Op::Jmp | Op::Call => Some(0),
+            Op::Jt | Op::Jf => Some(1),
+            _ => None,
+        }
+    }
+    /// Operand `i` (0-based) of the instruction at `ip`, as stored, i.e. without resolving registers.
+    pub fn operand(&self, ip: u16, i: u16) -> Option<u16> {
+        match DecodedOp::from(self.try_get(ip)?) {
+            DecodedOp::Known(op) if i < Vm::arg_count(op) => self.try_get(ip.checked_add(1 + i)?),
+            _ => None,
+        }
+    }
+    /// Writes the disassembly of the instructions starting in `from..to`, one per line.
+    pub fn write_disassembly<W: Write>(&self, out: &mut W, from: u16, to: u16, opts: &DisasmOptions)
+                                       -> io::Result<()> {
+        let mut my_ip = from;
+        while my_ip < to {
+            match self.disassemble_one_with(my_ip, opts) {
+                Some((line, next)) => {
+                    if let Some(label) = self.labels.get(&my_ip) {
+                        writeln!(out, "{}:", label)?;
+                    }
+                    writeln!(out, "{}", line)?;
+                    my_ip = next;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+    /// The instruction at `ip` as one line of disassembly, and the address following it.
+    /// Words that aren't opcodes are shown as plain data. `None` if there's nothing at `ip`.
+    pub fn disassemble_one(&self, ip: u16) -> Option<(String, u16)> {
+        self.disassemble_one_with(ip, &DisasmOptions::default())
+    }
+    /// An operand as it reads now: a literal as itself, a register as `rN=value`.
+    fn describe_operand(&self, arg: u16) -> String {
+        if arg >= 32768 {
+            format!("r{}={}", arg - 32768, self.try_get(arg).unwrap_or(0))
+        } else {
+            format!("{}", arg)
+        }
+    }
+    /// What a `Rmem`/`Wmem` at `ip` would access if run now, as a comment.
+    fn memory_annotation(&self, op: Op, ip: u16) -> Option<String> {
+        let resolve = |arg: u16| if arg >= 32768 { self.try_get(arg) } else { Some(arg) };
+        match op {
+            Op::Rmem => {
+                let src = self.try_get(ip + 2)?;
+                let value = match resolve(src).and_then(|a| self.try_get(a)) {
+                    Some(v) => v.to_string(),
+                    None => "unmapped".to_string(),
+                };
+                Some(format!("  // [{}] = {}", self.describe_operand(src), value))
+            }
+            Op::Wmem => {
+                let (dest, value) = (self.try_get(ip + 1)?, self.try_get(ip + 2)?);
+                Some(format!("  // [{}] <- {}", self.describe_operand(dest), self.describe_operand(value)))
+            }
+            _ => None,
+        }
+    }
+    /// The instruction at `ip` and the address following it. `None` if there's nothing at `ip` or
+    /// the word there isn't an opcode.
+    pub fn decode(&self, ip: u16) -> Option<(Instruction, u16)> {
+        match DecodedOp::from(self.try_get(ip)?) {
+            DecodedOp::Known(op) => {
+                let c = Vm::arg_count(op);
+                let args = self.read_mem_slice(ip.checked_add(1)?, c);
+                Some((Instruction { addr: ip, op, args }, ip + 1 + c))
+            }
+            DecodedOp::Unknown(_) => None,
+        }
+    }
+    /// `disassemble_one`, with the extras turned on in `opts`.
+    pub fn disassemble_one_with(&self, ip: u16, opts: &DisasmOptions) -> Option<(String, u16)> {
+        let mut ans = format!("@{} ", ip);
+        let (ins, next) = match self.decode(ip) {
+            Some(decoded) => decoded,
+            None => {
+                ans += &format!("{}", self.try_get(ip)?);
+                return Some((ans, ip + 1));
+            }
+        };
+        ans += &format!("{:?}", ins.op);
+        for i in 0..Vm::arg_count(ins.op) {
+            match ins.args.get(usize::from(i)) {
+                Some(arg) if Vm::jump_operand(ins.op) == Some(i) && self.labels.contains_key(arg) => {
+                    ans += &format!(" {}", self.labels[arg])
+                }
+                Some(arg) => ans += &format!(" {}", arg),
+                None => ans += " ?",
+            }
+        }
+        if opts.annotate_memory {
+            ans += &self.memory_annotation(ins.op, ip).unwrap_or_default();
+        }
+        Some((ans, next))
+    }
+    /// Up to `len` consecutive words starting at `addr`, stopping early at the end of memory.
+    pub fn read_mem_slice(&self, addr: u16, len: u16) -> Vec<u16> {
+        (0..len).map_while(|i| self.try_get(addr.wrapping_add(i))).collect()
+    }
+    /// `len` words from `addr`, taking the low byte of each as an ASCII char.
+    pub fn read_mem_string(&self, addr: u16, len: u16) -> String {
+        self.read_mem_slice(addr, len).into_iter().map(|w| char::from(w as u8)).collect()
+    }
+    /// 32-bit FNV-1a over the little-endian bytes of addresses 0..32768 as the program currently sees
+    /// them (rom plus overlay), with unmapped addresses counted as 0.
+    pub fn program_checksum(&self) -> u32 {
+        (0..32768_u16)
+            .flat_map(|a| self.try_get(a).unwrap_or(0).to_le_bytes().to_vec())
+            .fold(0x811c_9dc5_u32, |h, b| (h ^ u32::from(b)).wrapping_mul(0x0100_0193))
+    }
+    /// Every address at which `pattern` appears as consecutive words of rom plus overlay.
+    pub fn find_sequence(&self, pattern: &[u16]) -> Vec<u16> {
+        let end = self.memory.len();
+        let words: Vec<Option<u16>> = (0..end).map(|a| self.try_get(a as u16)).collect();
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+        words.windows(pattern.len())
+            .enumerate()
+            .filter(|(_, w)| w.iter().zip(pattern).all(|(a, b)| *a == Some(*b)))
+            .map(|(a, _)| a as u16)
+            .collect()
+    }
+    /// Every instruction from address 0 by linear sweep, as the disassembly lists them: its address,
+    /// op and operands. A word that isn't an opcode is yielded on its own as `Unknown`, so data can
+    /// show up as instructions.
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, DecodedOp, Vec<u16>)> + '_ {
+        let mut ip = 0_u16;
+        std::iter::from_fn(move || {
+            if ip >= 32768 {
+                return None;
+            }
+            let op = DecodedOp::from(self.try_get(ip)?);
+            let args = match op {
+                DecodedOp::Known(op) => self.read_mem_slice(ip + 1, Vm::arg_count(op)),
+                DecodedOp::Unknown(_) => Vec::new(),
+            };
+            let at = ip;
+            ip += 1 + args.len() as u16;
+            Some((at, op, args))
+        })
+    }
+    /// `(call address, target)` for every `Call` to a literal address in the linear disassembly.
+    /// Calls through a register are left out, as their target isn't known statically.
+    pub fn call_sites(&self) -> Vec<(u16, u16)> {
+        self.instructions()
+            .filter(|(_, op, args)| *op == DecodedOp::Known(Op::Call) && args.len() == 1 && args[0] < 32768)
+            .map(|(addr, _, args)| (addr, args[0]))
+            .collect()
+    }
+    /// Each literal target of a `Call`, `Jmp`, `Jt` or `Jf` in the linear disassembly, with the
+    /// addresses of the instructions that go there. Targets held in registers are left out; see
+    /// `dynamic_jumps`.
+    pub fn xrefs(&self) -> BTreeMap<u16, Vec<u16>> {
+        let mut ans: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+        for (addr, target) in self.jump_targets() {
+            if target < 32768 {
+                ans.entry(target).or_default().push(addr);
+            }
+        }
+        ans
+    }
+    /// Addresses of the `Call`s and jumps in the linear disassembly whose target is a register.
+    pub fn dynamic_jumps(&self) -> Vec<u16> {
+        self.jump_targets().filter(|&(_, target)| target >= 32768).map(|(addr, _)| addr).collect()
+    }
+    /// `(address, target operand)` for every instruction with a jump target.
+    fn jump_targets(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.instructions().filter_map(|(addr, op, args)| match op {
+            DecodedOp::Known(op) => Vm::jump_operand(op).and_then(|i| args.get(usize::from(i))).map(|&t| (addr, t)),
+            DecodedOp::Unknown(_) => None,
+        })
+    }
+    /// The stack from the top down, with each entry classified as a return address or data. An entry
+    /// is taken as a return address if the two words before it are a `Call`, which data can
+    /// occasionally also match.
+    pub fn backtrace(&self) -> Vec<StackEntry> {
+        self.stack.iter().rev()
+            .map(|&addr| {
+                let call_site = addr.checked_sub(2).filter(|&a| self.try_get(a) == Some(Op::Call as u16));
+                match (call_site, call_site.and_then(|a| self.try_get(a + 1))) {
+                    (Some(call_site), Some(callee)) => StackEntry::Return { addr, call_site, callee },
+                    _ => StackEntry::Data(addr),
+                }
+            })
+            .collect()
+    }
+    /// Addresses (opcodes and their operands) reachable by following control flow from address 0.
+    ///
+    /// Both sides of `Jt`/`Jf` are followed, and a `Call` is assumed to return to the instruction
+    /// after it. Jumps through registers can't be resolved statically and are not followed.
+    pub fn reachable_code(&self) -> BTreeSet<u16> {
+        let mut seen = BTreeSet::new();
+        let mut to_visit = vec![0_u16];
+        while let Some(addr) = to_visit.pop() {
+            if seen.contains(&addr) {
+                continue;
+            }
+            let op = match self.try_get(addr).map(DecodedOp::from) {
+                Some(DecodedOp::Known(op)) => op,
+                _ => continue,
+            };
+            let c = Vm::arg_count(op);
+            let args = (1..=c).map(|i| self.try_get(addr + i)).collect::<Option<Vec<u16>>>();
+            let args = match args {
+                Some(args) => args,
+                None => continue,
+            };
+            seen.extend(addr..=addr + c);
+            let next = addr + 1 + c;
+            let literal = |a: u16| if a < 32768 { Some(a) } else { None };
+            match op {
+                Op::Halt | Op::Ret => (),
+                Op::Jmp => to_visit.extend(literal(args[0])),
+                Op::Call => {
+                    to_visit.extend(literal(args[0]));
+                    to_visit.push(next);
+                }
+                Op::Jt | Op::Jf => {
+                    to_visit.extend(literal(args[1]));
+                    to_visit.push(next);
+                }
+                _ => to_visit.push(next),
+            }
+        }
+        seen
+    }
+    /// The instruction at the IP, which the next `step` will execute. `None` if the IP isn't at an
+    /// opcode.
+    pub fn peek_instruction(&self) -> Option<Instruction> {
+        self.decode(self.instruction_pointer).map(|(ins, _)| ins)
+    }
+    pub fn peek_op(&self) -> Result<Op, VmError> {
+        let word = self.get(self.instruction_pointer)?;
+        word.try_into().map_err(|_| VmError::UnknownOpcode(word))
+    }
+    pub fn take_output(&mut self) -> String {
+        let mut ans = String::new();
+        std::mem::swap(&mut self.output, &mut ans);
+        ans
+    }
+    /// Every address whose overlay entry differs between `self` and `other`, in address order.
+    pub fn diff_memory(&self, other: &Vm) -> Vec<(u16, MemoryChange)> {
+        (0..max(self.memory.len(), other.memory.len()))
+            .map(|k| k as u16)
+            .filter_map(|k| match (self.overlay_get(k), other.overlay_get(k)) {
+                (None, Some(_)) => Some((k, MemoryChange::Overlaid)),
+                (Some(_), None) => Some((k, MemoryChange::Reverted)),
+                (Some(a), Some(b)) if a != b => Some((k, MemoryChange::Changed)),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Whether the next instruction is an `In` with no input queued, which is where `run_to_input`
+    /// stops to wait for more. Checked before every instruction, so it compares the raw word rather
+    /// than decoding it, leaving that to `step`.
+    pub fn needs_input(&self) -> bool {
+        self.running && !self.input_closed && self.input.is_empty()
+            && self.try_get(self.instruction_pointer) == Some(Op::In as u16)
+    }
+    pub fn run_to_input(&mut self, running: Arc<AtomicBool>) -> Result<RunStatus, StepError> {
+        self.run(running, true)
+    }
+    /// Like `run_to_input`, but runs straight through breakpoints and watched writes.
+    pub fn run_through_breakpoints(&mut self, running: Arc<AtomicBool>) -> Result<RunStatus, StepError> {
+        self.run(running, false)
+    }
+    fn run(&mut self, running: Arc<AtomicBool>, stop_at_breaks: bool) -> Result<RunStatus, StepError> {
+        running.store(true, Ordering::SeqCst);
+        self.writes.clear();
+        let mut resuming = self.stopped_at.take() == Some(self.instruction_pointer);
+        self.watch_hit = None;
+        let status = loop {
+            if self.waiting_for_input {
+                break Ok(RunStatus::WaitingForInput);
+            }
+            if !self.running {
+                break Ok(RunStatus::Halted);
+            }
+            if !running.load(Ordering::SeqCst) {
+                break Ok(RunStatus::Interrupted);
+            }
+            if self.needs_input() {
+                break Ok(RunStatus::WaitingForInput);
+            }
+            if stop_at_breaks && !resuming && self.breakpoints.contains(&self.instruction_pointer) {
+                self.stopped_at = Some(self.instruction_pointer);
+                break Ok(RunStatus::Breakpoint(self.instruction_pointer));
+            }
+            resuming = false;
+            let ip = self.instruction_pointer;
+            if let Err(e) = self.step() {
+                break Err(e);
+            }
+            if let Some((addr, old, new)) = self.watch_hit.take() {
+                if stop_at_breaks {
+                    break Ok(RunStatus::Watchpoint { addr, old, new, ip });
+                }
+            }
+        };
+        running.store(false, Ordering::SeqCst);
+        status
+    }
+    /// Steps one instruction, except that a `Call` is run until it returns: that is, until the stack
+    /// is back to its depth before the call. Gives up after `budget` instructions.
+    pub fn step_over(&mut self, running: Arc<AtomicBool>, budget: u64) -> Result<StepOverStatus, StepError> {
+        running.store(true, Ordering::SeqCst);
+        let depth = self.stack.len();
+        let is_call = self.peek_op() == Ok(Op::Call);
+        let end = self.instruction_count + budget;
+        let status = loop {
+            if self.waiting_for_input || self.needs_input() {
+                break Ok(StepOverStatus::WaitingForInput);
+            }
+            if !self.running {
+                break Ok(StepOverStatus::Halted);
+            }
+            if !running.load(Ordering::SeqCst) {
+                break Ok(StepOverStatus::Interrupted);
+            }
+            if self.instruction_count >= end {
+                break Ok(StepOverStatus::OutOfBudget);
+            }
+            if let Err(e) = self.step() {
+                break Err(e);
+            }
+            if !is_call || self.stack.len() <= depth {
+                break Ok(StepOverStatus::Done);
+            }
+        };
+        running.store(false, Ordering::SeqCst);
+        status
+    }
+    /// Steps silently until `instruction_count` reaches `target`. Only meaningful when replaying from
+    /// a snapshot whose queued input got the original run at least that far.
+    pub fn run_to_count(&mut self, target: u64) -> Result<(), StepError> {
+        let live_output = self.live_output;
+        self.live_output = false;
+        let mut result = Ok(());
+        while self.running && self.instruction_count < target {
+            if let Err(e) = self.step() {
+                result = Err(e);
+                break;
+            }
+        }
+        self.live_output = live_output;
+        result
+    }
+    /// Runs until the program halts, `running` is cleared, the queued input runs out, or a step
+    /// fails, and returns everything printed along with which of those it was. An `In` with
+    /// nothing queued is left unexecuted, as `WaitingForInput`, rather than reading stdin.
+    pub fn run_to_halt(&mut self, running: Arc<AtomicBool>) -> (String, Result<RunStatus, StepError>) {
+        running.store(true, Ordering::SeqCst);
+        let status = loop {
+            if !self.running {
+                break Ok(RunStatus::Halted);
+            }
+            if !running.load(Ordering::SeqCst) {
+                break Ok(RunStatus::Interrupted);
+            }
+            if self.input.is_empty() && self.try_get(self.instruction_pointer) == Some(Op::In as u16) {
+                break Ok(RunStatus::WaitingForInput);
+            }
+            if let Err(e) = self.step() {
+                break Err(e);
+            }
+        };
+        running.store(false, Ordering::SeqCst);
+        (self.take_output(), status)
+    }
+}
+#[allow(dead_code)]
+mod ack;
+
+/// How many pre-run snapshots `step-back` keeps to replay from.
+const MAX_CHECKPOINTS: usize = 64;
+/// Replays longer than this are refused rather than left to grind.
+const MAX_REPLAY: u64 = 20_000_000;
+/// Replays longer than this get a warning, as they can take a noticeable while.
+const SLOW_REPLAY: u64 = 1_000_000;
+
+/// How many manual `set`s `undo-set` can take back.
+const MAX_SET_UNDO: usize = 32;
+
+/// How many instructions `next` runs a call for before giving up on it returning.
+const STEP_OVER_BUDGET: u64 = 100_000_000;
+
+/// How many instructions `view`, `follow` and `back` show.
+const VIEW_LINES: usize = 12;
+
+fn print_listing(vm: &Vm, addr: u16, opts: &DisasmOptions) {
+    let mut ip = addr;
+    for _ in 0..VIEW_LINES {
+        match vm.disassemble_one_with(ip, opts) {
+            Some((line, next)) => {
+                println!("{}", line);
+                ip = next;
+            }
+            None => break,
+        }
+    }
+}
+
+/// Lines from stdin, read on their own thread so that the prompt can notice a quit from Ctrl-C.
+pub fn stdin_lines() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut s = String::new();
+        match stdin().read_line(&mut s) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => if tx.send(s).is_err() { break },
+        }
+    });
+    rx
+}
+
+/// The next line from `lines`, or `None` once `quit` is set or the input has ended.
+fn next_line(lines: &Receiver<String>, quit: &AtomicBool) -> Option<String> {
+    loop {
+        if quit.load(Ordering::SeqCst) {
+            return None;
+        }
+        match lines.recv_timeout(Duration::from_millis(100)) {
+            Ok(s) => return Some(s),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+pub fn push_checkpoint<'a>(checkpoints: &mut VecDeque<Vm<'a>>, vm: &Vm<'a>) {
+    if checkpoints.len() == MAX_CHECKPOINTS {
+        checkpoints.pop_front();
+    }
+    checkpoints.push_back(vm.clone());
+}
+
+/// An address typed at the prompt: a number, or `r0`..`r7` for the registers.
+fn parse_addr(s: &str) -> Option<u16> {
+    match s.strip_prefix('r') {
+        Some(reg) => reg.parse::<u16>().ok().filter(|&r| r < 8).map(|r| 32768 + r),
+        None => s.parse().ok(),
+    }
+}
+
+/// `<start>..<end>` (end exclusive) or a single address, as a half-open range. Either end can be a
+/// register, so `r0..r8` covers all of them.
+fn parse_addr_range(s: &str) -> Option<(u16, u16)> {
+    match s.split_once("..") {
+        Some((start, end)) => {
+            let start = parse_addr(start)?;
+            let end = if end == "r8" { 32776 } else { parse_addr(end)? };
+            Some((start, end)).filter(|&(start, end)| start < end)
+        }
+        None => parse_addr(s).and_then(|a| Some((a, a.checked_add(1)?))),
+    }
+}
+
+/// Collapses ascending addresses into inclusive `(start, end)` runs.
+fn address_ranges<I: Iterator<Item = u16>>(addrs: I) -> Vec<(u16, u16)> {
+    addrs.map(|a| (a, a))
+        .coalesce(|(a, b), (c, d)| if b + 1 == c { Ok((a, d)) } else { Err(((a, b), (c, d))) })
+        .collect()
+}
+
+/// Memory for a `Vm`: `rom` out to at least the registers, with `writes` applied over it.
+fn memory_over(rom: &[u16], writes: impl Iterator<Item = (u16, u16)>) -> Vec<Option<u16>> {
+    let mut memory: Vec<Option<u16>> = rom.iter().cloned().map(Some).collect();
+    memory.resize(max(memory.len(), 32776), None);
+    for (address, value) in writes {
+        let i = usize::from(address);
+        if i >= memory.len() {
+            memory.resize(i + 1, None);
+        }
+        memory[i] = Some(value);
+    }
+    memory
+}
+
+/// Words that look like codes: 12 letters and digits, mixing upper and lower case. A capital
+/// only at the start doesn't count, so that words like "Headquarters" aren't picked up.
+pub fn find_codes(output: &str) -> Vec<String> {
+    output.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| w.len() == 12
+            && w.chars().skip(1).any(|c| c.is_ascii_uppercase())
+            && w.chars().any(|c| c.is_ascii_lowercase()))
+        .map(|w| w.to_string())
+        .collect()
+}
+
+/// Reads a program as little-endian 16-bit words, as for `words_from_bytes`.
+pub fn load_program(path: &str) -> io::Result<Vec<u16>> {
+    let mut file = File::open(path)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    words_from_bytes(&data).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+/// Turns raw bytes into little-endian 16-bit words. An odd number of bytes is rejected as
+/// `InvalidData` rather than guessing what the last byte was meant to be.
+fn words_from_bytes(data: &[u8]) -> io::Result<Vec<u16>> {
+    if !data.len().is_multiple_of(2) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("{} bytes isn't a whole number of words", data.len())));
+    }
+    Ok(data
+        .chunks(2)
+        .map(|s| {
+            let hi: u16 = s[1] as u16;
+            let lo: u16 = s[0] as u16;
+            hi << 8 | lo
+        }).collect_vec())
+}
+
+const PARTIAL_SOLUTION: &str = "doorway
+north
+north
+bridge
+continue
+down
+east
+take empty lantern
+west
+west
+passage
+ladder
+west
+south
+north
+take can
+west
+ladder
+use can
+use lantern
+darkness
+continue
+west
+west
+west
+west
+north
+take red coin
+north
+east
+take concave coin
+down
+take corroded coin
+up
+west
+west
+up
+take shiny coin
+down
+take blue coin
+east
+use blue coin
+use red coin
+use shiny coin
+use concave coin
+use corroded coin
+north
+take teleporter
+";
+
+
+/*
+        |
+        b
+        |
+   c   -a
+   |    |
+   c----L-
+   |    |
+  -d-
+   |
+a: twisty maze of little passages, all alike
+b: maze of little twisty passages, all alike
+c: little maze of twisty passages, all alike
+d: twisty alike of little passages, all maze
+*/
+
+
+/* vault
+
+        * 8 -  1 (Vault: 30)
+
+        4 * 11 *
+
+        + 4 -  18
+
+ Orb 22 O - 9  *
+
+ 22
+
+
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    /// Small xorshift generator so the fuzz runs are reproducible without pulling in `rand`.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        /// Mostly opcodes, registers and small literals, with the occasional arbitrary word.
+        fn word(&mut self) -> u16 {
+            let r = self.next();
+            match r % 8 {
+                0..=3 => (r >> 8) as u16 % 22,
+                4 | 5 => 32768 + (r >> 8) as u16 % 8,
+                6 => (r >> 8) as u16 % 64,
+                _ => (r >> 8) as u16,
+            }
+        }
+    }
+
+    /// Runs `vm` for at most `budget` instructions, stopping early if it halts or waits for input.
+    fn run_bounded(vm: &mut Vm, budget: usize) {
+        for _ in 0..budget {
+            let ip = vm.instruction_pointer;
+            if !vm.running || (vm.try_get(ip) == Some(Op::In as u16) && vm.input.is_empty()) {
+                return;
+            }
+            if vm.step().is_err() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn new_with_registers_presets_registers() {
+        // add r2 r0 r1; halt
+        let program = [9, 32770, 32768, 32769, 0];
+        let mut vm = Vm::new_with_registers(&program, [4, 1, 0, 0, 0, 0, 0, 9]);
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert_eq!(vm.try_get(32770), Some(5));
+        assert_eq!(vm.try_get(32775), Some(9));
+        assert_eq!(vm.registers(), [4, 1, 5, 0, 0, 0, 0, 9]);
+        vm.set(32775, 0);
+        assert_eq!(vm.registers()[7], 0);
+    }
+
+    #[test]
+    fn snapshots_restore_over_a_flashed_rom() {
+        let program = [21, 21, 21, 0];
+        let mut vm = Vm::new(&program);
+        vm.set(1, 9);
+        vm.set(32770, 4);
+        vm.stack.push(5);
+        let mut shared: Arc<[u16]> = Arc::from(&program[..]);
+        let before = vm.snapshot(&mut shared);
+        let saved = vm.clone();
+        vm.set(2, 7);
+        vm.flash_rom();
+        vm.stack.clear();
+        vm.restore(&before);
+        assert!(vm == saved);
+        assert_eq!(before.overlay_get(1), Some(9));
+        assert_eq!(before.overlay_get(0), None);
+        assert_eq!(vm.snapshot(&mut shared).diff_memory(&before), vec![]);
+    }
+
+    #[test]
+    fn snapshot_diff_sees_stack_depth() {
+        let program = [21, 21, 21, 0];
+        let mut shared: Arc<[u16]> = Arc::from(&program[..]);
+        let mut a = Vm::new(&program);
+        a.stack = vec![1, 2];
+        let mut b = a.clone();
+        b.stack.push(3);
+        let (sa, sb) = (a.snapshot(&mut shared), b.snapshot(&mut shared));
+        assert_eq!(sa.diff_memory(&sb), vec![]);
+        assert_eq!(sa.diff_ip(&sb), None);
+        assert_eq!(sa.diff_stack(&sb), Some((2, &[][..], &[3][..])));
+        assert_eq!(sa.diff_stack(&sa), None);
+        b.stack[0] = 9;
+        b.instruction_pointer = 2;
+        let sb = b.snapshot(&mut shared);
+        assert_eq!(sa.diff_stack(&sb), Some((0, &[1, 2][..], &[9, 2, 3][..])));
+        assert_eq!(sa.diff_ip(&sb), Some((0, 2)));
+    }
+
+    #[test]
+    fn diff_memory_labels_overlay_changes() {
+        let program = [21, 21, 21, 0];
+        let mut a = Vm::new(&program);
+        a.set(0, 10);
+        a.set(1, 20);
+        let mut b = a.clone();
+        b.set(0, 21);
+        b.set(1, 22);
+        b.set(2, 30);
+        assert_eq!(a.diff_memory(&b), vec![
+            (0, MemoryChange::Reverted),
+            (1, MemoryChange::Changed),
+            (2, MemoryChange::Overlaid),
+        ]);
+        assert_eq!(b.diff_memory(&a)[2], (2, MemoryChange::Reverted));
+    }
+
+    fn step_error(program: &[u16]) -> VmError {
+        let mut vm = Vm::new(program);
+        vm.live_output = false;
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap_err().error
+    }
+
+    #[test]
+    fn step_reports_unknown_opcode() {
+        assert_eq!(step_error(&[21, 22]), VmError::UnknownOpcode(22));
+    }
+
+    #[test]
+    fn step_reports_stack_underflow() {
+        let mut vm = Vm::new(&[21, 3, 32768]);
+        vm.step().unwrap();
+        assert_eq!(vm.step(), Err(StepError { ip: 1, error: VmError::StackUnderflow }));
+    }
+
+    #[test]
+    fn step_reports_bad_address() {
+        // rmem r0 30000
+        assert_eq!(step_error(&[15, 32768, 30000]), VmError::BadAddress(30000));
+        // runs off the end of the program
+        assert_eq!(step_error(&[21]), VmError::BadAddress(1));
+    }
+
+    #[test]
+    fn step_reports_invalid_char() {
+        // rmem r0 5; out r0; (a lone surrogate)
+        assert_eq!(step_error(&[15, 32768, 5, 19, 32768, 0xD800]), VmError::InvalidChar(0xD800));
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction_once_per_visit() {
+        // add r0 r0 1; jmp 0
+        let program = [9, 32768, 32768, 1, 6, 0];
+        let mut vm = Vm::new(&program);
+        let running = Arc::new(AtomicBool::new(true));
+        vm.add_breakpoint(0);
+        assert_eq!(vm.run_to_input(running.clone()), Ok(RunStatus::Breakpoint(0)));
+        assert_eq!(vm.instruction_count(), 0);
+        assert_eq!(vm.run_to_input(running.clone()), Ok(RunStatus::Breakpoint(0)));
+        assert_eq!(vm.try_get(32768), Some(1));
+        vm.add_breakpoint(4);
+        assert_eq!(vm.run_to_input(running.clone()), Ok(RunStatus::Breakpoint(4)));
+        assert_eq!(vm.try_get(32768), Some(2));
+        assert!(vm.remove_breakpoint(0));
+        assert!(!vm.remove_breakpoint(0));
+        assert_eq!(vm.breakpoints(), vec![4]);
+    }
+
+    #[test]
+    fn watchpoints_stop_after_a_change() {
+        // set r7 5; set r7 5; wmem 20 9; halt
+        let program = [1, 32775, 5, 1, 32775, 5, 16, 20, 9, 0];
+        let mut vm = Vm::new_zeroed(&program);
+        let running = Arc::new(AtomicBool::new(true));
+        vm.add_watch(32775);
+        vm.add_watch(20);
+        assert_eq!(vm.run_to_input(running.clone()),
+                   Ok(RunStatus::Watchpoint { addr: 32775, old: Some(0), new: 5, ip: 0 }));
+        // Writing the same value again isn't a change.
+        assert_eq!(vm.run_to_input(running.clone()),
+                   Ok(RunStatus::Watchpoint { addr: 20, old: Some(0), new: 9, ip: 6 }));
+        assert_eq!(vm.run_to_input(running.clone()), Ok(RunStatus::Halted));
+        assert_eq!(vm.watches(), vec![20, 32775]);
+    }
+
+    #[test]
+    fn op_counts_tally_each_op() {
+        // add r0 r0 1; eq r1 r0 3; jf r1 0; halt
+        let program = [9, 32768, 32768, 1, 4, 32769, 32768, 3, 8, 32769, 0, 0];
+        let mut vm = Vm::new(&program);
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert_eq!(vm.op_counts(), vec![(Op::Eq, 3), (Op::Jf, 3), (Op::Add, 3), (Op::Halt, 1)]);
+        vm.reset_op_counts();
+        assert_eq!(vm.op_counts(), vec![]);
+        assert_eq!(vm.instruction_count(), 10);
+    }
+
+    #[test]
+    fn hotspots_rank_addresses_by_executions() {
+        // 0: add r0 r0 1; 4: eq r1 r0 3; 8: jf r1 0; 11: halt
+        let program = [9, 32768, 32768, 1, 4, 32769, 32768, 3, 8, 32769, 0, 0];
+        let mut vm = Vm::new(&program);
+        vm.step().unwrap();
+        vm.set_profiling(true);
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert_eq!(vm.hotspots(3), vec![(4, 3), (8, 3), (0, 2)]);
+        vm.set_profiling(false);
+        assert_eq!(vm.hotspots(3), vec![]);
+    }
+
+    #[test]
+    fn disassemble_range_decodes_from_start() {
+        // 0: set r0 19; 3: out r0; 5: halt
+        let program = [1, 32768, 19, 19, 32768, 0];
+        let vm = Vm::new(&program);
+        let opts = DisasmOptions::default();
+        assert_eq!(vm.disassemble_range(3, 5, &opts), "@3 Out 32768\n");
+        // Starting inside the set reads its operand 19 as an out.
+        assert_eq!(vm.disassemble_range(2, 4, &opts), "@2 Out 19\n");
+        assert_eq!(vm.disassemble_range(0, 100, &opts), vm.disassemble(&opts));
+    }
+
+    #[test]
+    fn decode_gives_op_operands_and_next_address() {
+        // 0: set r0 19; 3: 99 (not an op); 4: out
+        let program = [1, 32768, 19, 99, 19];
+        let vm = Vm::new(&program);
+        assert_eq!(vm.decode(0), Some((Instruction { addr: 0, op: Op::Set, args: vec![32768, 19] }, 3)));
+        assert_eq!(vm.decode(3), None);
+        // The out's operand is past the end of the program.
+        assert_eq!(vm.decode(4), Some((Instruction { addr: 4, op: Op::Out, args: vec![] }, 6)));
+        assert_eq!(vm.disassemble_one(4), Some(("@4 Out ?".to_string(), 6)));
+    }
+
+    #[test]
+    fn labels_name_addresses_and_jump_targets() {
+        // 0: call 5; 2: jt r0 5; 5: ret
+        let program = [17, 5, 7, 32768, 5, 18];
+        let mut vm = Vm::new(&program);
+        vm.set_label(5, "routine");
+        vm.set_label(32768, "r0");
+        assert_eq!(vm.disassemble(&DisasmOptions::default()), "@0 Call routine\n@2 Jt 32768 routine\nroutine:\n@5 Ret\n");
+    }
+
+    #[test]
+    fn undo_steps_back_through_recorded_history() {
+        // add r0 r0 1; jmp 0
+        let program = [9, 32768, 32768, 1, 6, 0];
+        let mut vm = Vm::new(&program);
+        vm.step().unwrap();
+        vm.set_history_depth(2);
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!((vm.try_get(32768), vm.instruction_count()), (Some(3), 5));
+        assert!(vm.undo());
+        assert_eq!((vm.instruction_pointer, vm.try_get(32768), vm.instruction_count()), (0, Some(2), 4));
+        assert!(vm.undo());
+        assert_eq!((vm.instruction_pointer, vm.try_get(32768), vm.instruction_count()), (4, Some(2), 3));
+        assert!(!vm.undo());
+        vm.step().unwrap();
+        assert!(vm.undo());
+    }
+
+    #[test]
+    fn log_gets_output_even_when_not_live() {
+        // out 'h'; out 'i'; out '\n'; halt
+        let program = [19, 104, 19, 105, 19, 10, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        let path = std::env::temp_dir().join(format!("synacor-log-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        vm.set_log(Some(path)).unwrap();
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        vm.set_log(None).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hi\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn backtrace_tells_return_addresses_from_data() {
+        let program = [
+            17, 4,           // 0: call 4
+            0,               // 2: halt
+            0,               // 3: data
+            2, 3,            // 4: push 3
+            2, 1,            // 6: push 1
+            17, 32768,       // 8: call r0
+            0,               // 10: halt
+            0,               // 11: halt
+            21,              // 12: nop
+        ];
+        let mut vm = Vm::new(&program);
+        vm.set(32768, 12);
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.backtrace(), vec![
+            StackEntry::Return { addr: 10, call_site: 8, callee: 32768 },
+            StackEntry::Data(1),
+            StackEntry::Data(3),
+            StackEntry::Return { addr: 2, call_site: 0, callee: 4 },
+        ]);
+    }
+
+    #[test]
+    fn add_wraps_at_32768() {
+        // add r2 r0 r1
+        let program = [9, 32770, 32768, 32769];
+        let mut vm = Vm::new_with_registers(&program, [20000, 20000, 0, 0, 0, 0, 0, 0]);
+        vm.step().unwrap();
+        assert_eq!(vm.try_get(32770), Some(7232));
+    }
+
+    #[test]
+    fn mod_by_zero_is_an_error() {
+        // mod r0 5 0
+        assert_eq!(step_error(&[11, 32768, 5, 0]), VmError::DivisionByZero);
+    }
+
+    #[test]
+    fn saved_state_continues_identically() {
+        // in r0; out r0; add r1 r1 r0; push r1; jmp 0
+        let program = [20, 32768, 19, 32768, 9, 32769, 32769, 32768, 2, 32769, 6, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        vm.push_input("abc");
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("synacor-vm-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        vm.save_to_file(path).unwrap();
+        let mut loaded = Vm::load_from_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(loaded == vm);
+        for _ in 0..8 {
+            vm.step().unwrap();
+            loaded.step().unwrap();
+        }
+        assert!(loaded == vm);
+        assert_eq!(loaded.take_output(), "abc");
+    }
+
+    #[test]
+    fn step_over_runs_calls_to_their_return() {
+        let program = [
+            17, 6,           // 0: call 6
+            21,              // 2: noop
+            17, 11,          // 3: call 11
+            0,               // 5: halt
+            9, 32768, 32768, 1,  // 6: add r0 r0 1
+            18,              // 10: ret
+            6, 11,           // 11: jmp 11
+        ];
+        let mut vm = Vm::new(&program);
+        let running = Arc::new(AtomicBool::new(true));
+        assert_eq!(vm.step_over(running.clone(), 100), Ok(StepOverStatus::Done));
+        assert_eq!((vm.instruction_pointer, vm.instruction_count()), (2, 3));
+        assert_eq!(vm.try_get(32768), Some(1));
+        assert_eq!(vm.step_over(running.clone(), 100), Ok(StepOverStatus::Done));
+        assert_eq!((vm.instruction_pointer, vm.instruction_count()), (3, 4));
+        assert_eq!(vm.step_over(running.clone(), 100), Ok(StepOverStatus::OutOfBudget));
+        assert_eq!(vm.instruction_count(), 104);
+    }
+
+    #[test]
+    fn call_sites_lists_literal_calls() {
+        let program = [
+            17, 9,           // 0: call 9
+            17, 32768,       // 2: call r0
+            17, 9,           // 4: call 9
+            17, 10,          // 6: call 10
+            0,               // 8: halt
+            18,              // 9: ret
+            18,              // 10: ret
+        ];
+        let vm = Vm::new(&program);
+        assert_eq!(vm.call_sites(), vec![(0, 9), (4, 9), (6, 10)]);
+        assert_eq!(vm.instructions().count(), 7);
+    }
+
+    #[test]
+    fn xrefs_map_targets_to_callers_and_jumpers() {
+        let program = [
+            17, 10,          // 0: call 10
+            6, 32768,        // 2: jmp r0
+            7, 32768, 10,    // 4: jt r0 10
+            8, 0, 7,         // 7: jf 0 7
+            18,              // 10: ret
+        ];
+        let vm = Vm::new(&program);
+        let expected: BTreeMap<u16, Vec<u16>> = vec![(7, vec![7]), (10, vec![0, 4])].into_iter().collect();
+        assert_eq!(vm.xrefs(), expected);
+        assert_eq!(vm.dynamic_jumps(), vec![2]);
+    }
+
+    #[test]
+    fn reachable_code_skips_data() {
+        let program = [
+            6, 3,            // 0: jmp 3
+            1234,            // 2: data
+            7, 32768, 7,     // 3: jt r0 7
+            0,               // 6: halt
+            17, 10,          // 7: call 10
+            0,               // 9: halt
+            18,              // 10: ret
+            19, 65,          // 11: out 'A' (never reached)
+        ];
+        let vm = Vm::new(&program);
+        assert_eq!(vm.reachable_code(), (0..=10).filter(|&a| a != 2).collect());
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_stops_at_the_registers() {
+        let mut vm = Vm::new_zeroed(&[]);
+        for a in 0..32768 {
+            vm.set(a, Op::Nop as u16);
+        }
+        vm.live_output = false;
+        let err = vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap_err();
+        assert_eq!(err, StepError { ip: 32768, error: VmError::IpInRegisterSpace });
+        assert_eq!(vm.instruction_count(), 32768);
+    }
+
+    #[test]
+    fn from_file_matches_loading_the_program() {
+        let program = load_program("doc/challenge.bin").unwrap();
+        let vm = Vm::from_file("doc/challenge.bin").unwrap();
+        assert!(vm == Vm::new(&program));
+    }
+
+    #[test]
+    fn from_bytes_rejects_an_odd_length() {
+        let err = Vm::from_bytes(&[21, 0, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let vm = Vm::from_bytes(&[21, 0, 0, 0]).unwrap();
+        assert!(vm == Vm::new(&[21, 0]));
+    }
+
+    #[test]
+    fn find_codes_skips_capitalised_words() {
+        assert_eq!(find_codes("== Synacor Headquarters ==\n    MdFvooqjXBue\n"), vec!["MdFvooqjXBue".to_string()]);
+    }
+
+    #[test]
+    fn new_zeroed_reads_untouched_memory_as_zero() {
+        let program = [21, 0];
+        assert_eq!(Vm::new(&program).get(20000), Err(VmError::BadAddress(20000)));
+        let vm = Vm::new_zeroed(&program);
+        assert_eq!(vm.get(20000), Ok(0));
+        assert_eq!(vm.get(0), Ok(21));
+    }
+
+    #[test]
+    fn executed_addresses_cover_opcodes_and_operands() {
+        // 0: set r0 4; 3: wmem 1 0; 6: halt; 7: noop
+        let program = [1, 32768, 4, 16, 1, 0, 0, 21];
+        let mut vm = Vm::new(&program);
+        vm.set_self_modifying_warnings(true);
+        run_bounded(&mut vm, 10);
+        assert!((0..7).all(|a| vm.was_executed(a)));
+        assert!(!vm.was_executed(7));
+        assert_eq!(vm.get(1), Ok(0));
+        vm.reset();
+        assert!(!vm.was_executed(0));
+    }
+
+    #[test]
+    fn coverage_survives_save_and_restore() {
+        let program = [21, 21, 0, 19, 0];
+        let mut vm = Vm::new(&program);
+        run_bounded(&mut vm, 10);
+        assert_eq!(vm.executed_addresses(), vec![0, 1, 2]);
+        assert_eq!(vm.program_len(), 5);
+        let path = std::env::temp_dir().join(format!("synacor-coverage-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        vm.save_to_file(path).unwrap();
+        let restored = Vm::load_from_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(restored.executed_addresses(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn trace_resolves_registers_but_not_literals() {
+        // set r0 7; add r1 r0 32768; halt
+        let program = [1, 32768, 7, 9, 32769, 32768, 5, 0];
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.trace_line(0), "@0 Set r0=0 7");
+        let path = std::env::temp_dir().join(format!("synacor-trace-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        vm.set_trace_file(path).unwrap();
+        run_bounded(&mut vm, 10);
+        vm.set_trace(false);
+        let trace = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(trace, "@0 Set r0=0 7\n@3 Add r1=0 r0=7 5\n@7 Halt\n");
+    }
+
+    #[test]
+    fn tracked_writes_record_overlay_and_revert() {
+        // wmem 10 5; wmem 11 7; halt; then data 0 7
+        let program = [16, 10, 5, 16, 11, 7, 0, 0, 0, 0, 0, 7];
+        let mut vm = Vm::new(&program);
+        vm.set_track_writes(true);
+        vm.set(11, 3);
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        let expected: BTreeMap<u16, MemoryChange> =
+            vec![(10, MemoryChange::Overlaid), (11, MemoryChange::Reverted)].into_iter().collect();
+        assert_eq!(vm.writes(), &expected);
+    }
+
+    #[test]
+    fn program_checksum_is_pinned_and_sees_patches() {
+        let program = [19, 72, 19, 105, 0];
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.program_checksum(), 391_447_036);
+        assert_eq!(vm.program_checksum(), Vm::new_zeroed(&program).program_checksum());
+        vm.set(1, 73);
+        assert_ne!(vm.program_checksum(), 391_447_036);
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modifying_code() {
+        let program = [
+            17, 10,         // 0: call 10
+            16, 10, 0,      // 2: wmem 10 0 (turn the routine into a halt)
+            17, 10,         // 5: call 10
+            19, 88,         // 7: out 'X'
+            0,              // 9: halt
+            19, 65,         // 10: out 'A'
+            18,             // 12: ret
+        ];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert_eq!(vm.take_output(), "A");
+        assert_eq!(vm.instruction_pointer, 11);
+    }
+
+    #[test]
+    fn parse_addr_maps_registers() {
+        assert_eq!(parse_addr("r7"), Some(32775));
+        assert_eq!(parse_addr("r0"), Some(32768));
+        assert_eq!(parse_addr("6027"), Some(6027));
+        assert_eq!(parse_addr("r8"), None);
+        assert_eq!(parse_addr("x"), None);
+    }
+
+    #[test]
+    fn parse_addr_range_takes_single_addresses_and_ranges() {
+        assert_eq!(parse_addr_range("5"), Some((5, 6)));
+        assert_eq!(parse_addr_range("r7"), Some((32775, 32776)));
+        assert_eq!(parse_addr_range("10..13"), Some((10, 13)));
+        assert_eq!(parse_addr_range("r0..r8"), Some((32768, 32776)));
+        assert_eq!(parse_addr_range("13..10"), None);
+        assert_eq!(parse_addr_range("10.."), None);
+        assert_eq!(parse_addr_range("65535"), None);
+    }
+
+    #[test]
+    fn reset_returns_to_the_initial_state() {
+        let program = [9, 32768, 32768, 1, 19, 65, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        vm.reset();
+        let mut fresh = Vm::new(&program);
+        fresh.live_output = false;
+        assert_eq!(vm, fresh);
+        assert_eq!(vm.instruction_count(), 0);
+        assert_eq!(vm.try_get(32768), Some(0));
+    }
+
+    #[test]
+    fn annotates_rmem_and_wmem() {
+        let program = [
+            15, 32768, 8,         // 0: rmem r0 8
+            16, 32769, 32770,     // 3: wmem r1 r2
+            0,                    // 6: halt
+            0,                    // 7
+            1234,                 // 8: data
+        ];
+        let vm = Vm::new_with_registers(&program, [0, 8, 5, 0, 0, 0, 0, 0]);
+        let opts = DisasmOptions { annotate_memory: true };
+        assert_eq!(vm.disassemble_one_with(0, &opts).unwrap().0, "@0 Rmem 32768 8  // [8] = 1234");
+        assert_eq!(vm.disassemble_one_with(3, &opts).unwrap().0, "@3 Wmem 32769 32770  // [r1=8] <- r2=5");
+        assert_eq!(vm.disassemble_one(0).unwrap().0, "@0 Rmem 32768 8");
+        let past_the_end = Vm::new(&[15, 32768, 100]);
+        assert_eq!(past_the_end.disassemble_one_with(0, &opts).unwrap().0, "@0 Rmem 32768 100  // [100] = unmapped");
+    }
+
+    #[test]
+    fn flush_policies() {
+        assert!(FlushPolicy::EveryNewline.should_flush('\n', 1));
+        assert!(!FlushPolicy::EveryNewline.should_flush('a', 100));
+        assert!(FlushPolicy::EveryChars(4).should_flush('a', 4));
+        assert!(!FlushPolicy::EveryChars(4).should_flush('a', 3));
+        assert!(FlushPolicy::EveryChars(4).should_flush('\n', 1));
+        assert!(!FlushPolicy::Never.should_flush('\n', 100));
+    }
+
+    #[test]
+    fn headless_in_stops_instead_of_reading_stdin() {
+        // in r0; out r0; halt
+        let program = [20, 32768, 19, 32768, 0];
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        vm.set_headless(true);
+        while vm.running {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.instruction_pointer, 0);
+        assert_eq!(vm.instruction_count(), 0);
+        assert_eq!(vm.run_to_input(Arc::new(AtomicBool::new(true))), Ok(RunStatus::WaitingForInput));
+        vm.push_input("A");
+        assert_eq!(vm.run_to_input(Arc::new(AtomicBool::new(true))), Ok(RunStatus::Halted));
+        assert_eq!(vm.take_output(), "A");
+    }
+
+    #[test]
+    fn in_after_input_ends_is_an_error() {
+        // in r0; in r0; halt
+        let program = [20, 32768, 20, 32768, 0];
+        let mut vm = Vm::new(&program);
+        vm.push_input("a");
+        vm.close_input();
+        let err = vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap_err();
+        assert_eq!(err, StepError { ip: 2, error: VmError::InputExhausted });
+        assert_eq!(vm.try_get(32768), Some(97));
+    }
+
+    #[test]
+    fn needs_input_only_at_in_with_nothing_queued() {
+        let program = [21, 20, 32768, 0];
+        let mut vm = Vm::new(&program);
+        assert!(!vm.needs_input());
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert!(vm.needs_input());
+        vm.push_input("x");
+        assert!(!vm.needs_input());
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        assert!(!vm.running);
+        assert!(!vm.needs_input());
+    }
+
+    #[test]
+    fn run_to_halt_stops_when_input_runs_out() {
+        let mut vm = Vm::from_file("doc/challenge.bin").unwrap();
+        vm.live_output = false;
+        vm.push_input(PARTIAL_SOLUTION);
+        let (output, status) = vm.run_to_halt(Arc::new(AtomicBool::new(true)));
+        assert_eq!(status, Ok(RunStatus::WaitingForInput));
+        assert!(output.contains("== Foothills =="));
+        assert!(output.contains("== Ruins =="));
+        assert!(output.ends_with("Taken.\n\nWhat do you do?\n"), "{}", output);
+        assert!(vm.running);
+        assert!(!vm.input_closed);
+        vm.push_input("inv\n");
+        let (output, _) = vm.run_to_halt(Arc::new(AtomicBool::new(true)));
+        assert!(output.contains("teleporter"), "{}", output);
+    }
+
+    #[test]
+    fn run_to_halt_tells_a_halt_from_an_error() {
+        let (output, status) = Vm::new(&[19, 65, 0]).run_to_halt(Arc::new(AtomicBool::new(true)));
+        assert_eq!((output.as_str(), status), ("A", Ok(RunStatus::Halted)));
+        let (_, status) = Vm::new(&[19, 65, 22]).run_to_halt(Arc::new(AtomicBool::new(true)));
+        assert_eq!(status, Err(StepError { ip: 2, error: VmError::UnknownOpcode(22) }));
+    }
+
+    #[test]
+    fn peek_instruction_does_not_advance() {
+        let mut vm = Vm::new(&[9, 32768, 32769, 4, 0]);
+        let expected = Instruction { addr: 0, op: Op::Add, args: vec![32768, 32769, 4] };
+        assert_eq!(vm.peek_instruction(), Some(expected.clone()));
+        assert_eq!(vm.peek_instruction(), Some(expected));
+        vm.step().unwrap();
+        assert_eq!(vm.peek_instruction().map(|ins| (ins.addr, ins.op)), Some((4, Op::Halt)));
+        vm.step().unwrap();
+        assert_eq!(vm.peek_instruction(), None);
+    }
+
+    #[test]
+    fn next_line_ends_with_the_input() {
+        let (tx, rx) = mpsc::channel();
+        tx.send("look\n".to_string()).unwrap();
+        drop(tx);
+        let quit = AtomicBool::new(false);
+        assert_eq!(next_line(&rx, &quit), Some("look\n".to_string()));
+        assert_eq!(next_line(&rx, &quit), None);
+    }
+
+    #[test]
+    fn unmapped_reads_as_zero_or_error() {
+        // rmem r0 100; halt
+        let program = [15, 32768, 100, 0];
+        let mut vm = Vm::new_with_registers(&program, [7, 0, 0, 0, 0, 0, 0, 0]);
+        vm.live_output = false;
+        vm.set_unmapped_read_mode(UnmappedReads::ZeroWithWarning);
+        assert_eq!(vm.run_to_input(Arc::new(AtomicBool::new(true))), Ok(RunStatus::Halted));
+        assert_eq!(vm.registers()[0], 0);
+        assert_eq!(vm.take_unmapped_reads(), vec![100]);
+        assert!(vm.take_unmapped_reads().is_empty());
+
+        let mut vm = Vm::new(&program);
+        vm.set_unmapped_read_mode(UnmappedReads::Error);
+        let err = vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap_err();
+        assert_eq!(err, StepError { ip: 0, error: VmError::BadAddress(100) });
+        assert!(vm.take_unmapped_reads().is_empty());
+    }
+
+    #[test]
+    fn feed_str_queues_verbatim_and_in_order() {
+        let mut vm = Vm::new(&[]);
+        vm.feed_str("ab\t");
+        vm.feed_str("c\r\n");
+        let queued: String = vm.input.iter().rev().collect();
+        assert_eq!(queued, "ab\tc\r\n");
+        vm.set_input_queue("xy");
+        assert_eq!(vm.input, vec!['y', 'x']);
+    }
+
+    #[test]
+    fn push_input_normalises_whitespace() {
+        let mut vm = Vm::new(&[]);
+        vm.push_input("take\ttablet\t\r\n");
+        vm.push_input("look  ");
+        let queued: String = vm.input.iter().rev().collect();
+        assert_eq!(queued, "take tablet\nlook");
+    }
+
+    #[test]
+    fn find_sequence_sees_rom_and_overlay() {
+        let mut vm = Vm::new(&[1, 32768, 6, 0, 1, 32768, 5]);
+        assert_eq!(vm.find_sequence(&[1, 32768, 6]), vec![0]);
+        vm.set(6, 6);
+        assert_eq!(vm.find_sequence(&[1, 32768, 6]), vec![0, 4]);
+        vm.set(8, 6);
+        assert_eq!(vm.find_sequence(&[6, 0]), vec![2]);
+    }
+
+    /// Fills programs with random words and checks `step()` never panics on them.
+    ///
+    /// `FUZZ_ITERATIONS` and `FUZZ_SEED` override the number of programs tried and the starting
+    /// seed, e.g. `FUZZ_ITERATIONS=100000 cargo test fuzz_step` for a longer run.
+    #[test]
+    fn fuzz_step() {
+        let iterations = std::env::var("FUZZ_ITERATIONS").ok().and_then(|s| s.parse().ok()).unwrap_or(2000);
+        let seed = std::env::var("FUZZ_SEED").ok().and_then(|s| s.parse().ok()).unwrap_or(0x2545_F491_4F6C_DD1D);
+        let mut rng = XorShift(seed);
+        for _ in 0..iterations {
+            let len = 1 + rng.next() as usize % 64;
+            let program = (0..len).map(|_| rng.word()).collect_vec();
+            let input: String = (0..rng.next() % 8).map(|_| (b'a' + (rng.next() % 26) as u8) as char).collect();
+            let mut vm = Vm::new(&program);
+            vm.live_output = false;
+            vm.input = input.chars().rev().collect();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run_bounded(&mut vm, 1000)));
+            assert!(result.is_ok(), "step() panicked on program {:?} with input {:?}", program, input);
+        }
+    }
+}
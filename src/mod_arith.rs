@@ -1,8 +1,17 @@
 use std::ops::{Rem, Shr};
 use std::fmt::Debug;
-use num::{Num, CheckedMul, CheckedAdd, CheckedSub};
+use num::{Num, CheckedAdd, CheckedSub};
 use num::traits::WrappingMul;
 
+/// `base^exp % modulus` by square-and-multiply. As with `mod_mul`, `T` must be wide enough to hold
+/// `(modulus - 1)^2`.
+///
+/// ```
+/// use synacor_challenge::mod_arith::mod_pow;
+/// assert_eq!(mod_pow(3_u32, 13, 1000), 323);
+/// assert_eq!(mod_pow(3_u32, 0, 1000), 1);
+/// assert_eq!(mod_pow(3_u32, 13, 1), 0);
+/// ```
 pub fn mod_pow<T>(mut base: T, mut exp: T, modulus: T) -> T
     where T: Num + Copy + Shr<Output = T> + From<u8> + PartialOrd + WrappingMul
 {
@@ -20,15 +29,64 @@ pub fn mod_pow<T>(mut base: T, mut exp: T, modulus: T) -> T
     }
     result
 }
+/// `a * b % m`. The product wraps rather than overflowing, so this is only correct when it fits
+/// in `T`; widen first (as `mod_mul_u16` does), or use `mod_mul_exact`, if it might not.
 pub fn mod_mul<T>(a : T, b: T, m : T) -> T
     where T : WrappingMul + Rem<Output = T> + Copy
 {
     a.wrapping_mul(&b) % m
 }
+/// `a * b % m` for any `a`, `b` and `m` that fit in `T`, by shift-and-add so no intermediate
+/// exceeds `m`. Slower than `mod_mul`, taking a step per bit of `b`.
+pub fn mod_mul_exact<T>(a: T, b: T, m: T) -> T
+    where T: Num + Copy + Shr<Output = T> + From<u8> + PartialOrd
+{
+    // x + y % m, for x and y already below m, without computing x + y.
+    let add = |x: T, y: T| if x >= m - y { x - (m - y) } else { x + y };
+    let (mut a, mut b) = (a % m, b);
+    let mut result = T::zero();
+    while b > T::zero() {
+        if b % 2.into() == T::one() {
+            result = add(result, a);
+        }
+        a = add(a, a);
+        b = b >> T::one();
+    }
+    result
+}
+/// The `x` with `a * x % m == 1`, by the extended Euclidean algorithm, or `None` if `a` and `m`
+/// share a factor. The coefficients are kept reduced mod `m` so unsigned `T` works; as with
+/// `mod_mul`, `T` must be wide enough to hold `m^2`.
+pub fn mod_inverse<T>(a: T, m: T) -> Option<T>
+    where T: Num + Copy + PartialOrd + WrappingMul
+{
+    let (mut r0, mut r1) = (m, a % m);
+    let (mut t0, mut t1) = (T::zero(), T::one() % m);
+    while r1 != T::zero() {
+        let q = r0 / r1;
+        let r2 = r0 - q * r1;
+        let qt = mod_mul(q, t1, m);
+        let t2 = if t0 >= qt { t0 - qt } else { m - (qt - t0) };
+        r0 = r1;
+        r1 = r2;
+        t0 = t1;
+        t1 = t2;
+    }
+    if r0 == T::one() { Some(t0) } else { None }
+}
+/// `mod_pow` fixed to the VM's modulus of 32768, widened to u32 internally so
+/// intermediate products can't overflow.
+pub fn mod_pow_u16(base: u16, exp: u16) -> u16 {
+    mod_pow(u32::from(base), u32::from(exp), 32768) as u16
+}
+/// `mod_mul` fixed to the VM's modulus of 32768, widened to u32 internally.
+pub fn mod_mul_u16(a: u16, b: u16) -> u16 {
+    mod_mul(u32::from(a), u32::from(b), 32768) as u16
+}
 pub fn mod_add<T>(a:&T, b: &T, m: T) -> T
     where T : CheckedAdd + Rem<Output = T> + Debug
 {
-    match a.checked_add(&b){
+    match a.checked_add(b){
         None => panic!("mod_add overflowed with {:?}+{:?}%{:?}",a,b,m),
         Some(ab) => ab % m
     }
@@ -43,3 +101,143 @@ pub fn mod_sub<T>(a: &T, b: &T, m: T) -> T
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference_pow(base: u64, exp: u64) -> u64 {
+        (0..exp).fold(1, |acc, _| acc * base % 32768)
+    }
+
+    #[test]
+    fn mod_pow_u16_matches_u64_reference() {
+        for &base in &[0, 1, 2, 3, 7, 255, 32767, 40000, 65535] {
+            for exp in 0..70 {
+                assert_eq!(u64::from(mod_pow_u16(base, exp)), reference_pow(base.into(), exp.into()),
+                           "{}^{}", base, exp);
+            }
+        }
+    }
+
+    #[test]
+    fn mod_pow_with_odd_and_prime_moduli() {
+        assert_eq!(mod_pow(2_u32, 10, 1000), 24);
+        assert_eq!(mod_pow(3_u32, 4, 5), 1);
+        assert_eq!(mod_pow(5_u32, 3, 13), 8);
+        assert_eq!(mod_pow(7_u32, 0, 9), 1);
+        assert_eq!(mod_pow(12_u32, 5, 1), 0);
+        for &m in &[3_u64, 7, 9, 10, 97, 1000, 32749, 65521] {
+            for base in (0..200).step_by(7) {
+                let mut expected = 1 % m;
+                for exp in 0..40 {
+                    assert_eq!(mod_pow(base, exp, m), expected, "{}^{} % {}", base, exp, m);
+                    expected = expected * base % m;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mod_pow_edge_cases() {
+        assert_eq!(mod_pow(3_u64, 13, 1000), 1_594_323 % 1000);
+        assert_eq!(mod_pow(0_u32, 0, 7), 1);
+        assert_eq!(mod_pow(0_u32, 5, 7), 0);
+        for base in 0..10_u32 {
+            assert_eq!(mod_pow(base, 0, 1), 0, "{}^0 % 1", base);
+            assert_eq!(mod_pow(base, 3, 1), 0, "{}^3 % 1", base);
+        }
+        assert_eq!(mod_pow_u16(3, 13), (1_594_323 % 32768) as u16);
+    }
+
+    #[test]
+    fn mod_add_and_sub_reduce() {
+        assert_eq!(mod_add(&30000_u16, &5000, 32768), 2232);
+        assert_eq!(mod_add(&65535_u16, &0, 32768), 32767);
+        assert_eq!(mod_sub(&10_u32, &3, 5), 2);
+        assert_eq!(mod_sub(&7_u8, &7, 3), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "mod_add overflowed")]
+    fn mod_add_panics_on_overflow() {
+        mod_add(&65535_u16, &1, 32768);
+    }
+
+    #[test]
+    #[should_panic(expected = "mod_sub underflowed")]
+    fn mod_sub_panics_on_underflow() {
+        mod_sub(&3_u32, &4, 5);
+    }
+
+    #[test]
+    fn mod_mul_reduces_products_that_fit() {
+        assert_eq!(mod_mul(6_u32, 7, 10), 2);
+        assert_eq!(mod_mul(0_u32, 12345, 97), 0);
+        assert_eq!(mod_mul(32767_u32, 32767, 32768), 1);
+        assert_eq!(mod_mul(9_u8, 9, 100), 81);
+    }
+
+    #[test]
+    fn mod_pow_large_exponents() {
+        // Fermat's little theorem: a^(p-1) = 1 mod p for prime p not dividing a.
+        let p = 1_000_000_007_u64;
+        for &a in &[2, 3, 12345, 999_999_999] {
+            assert_eq!(mod_pow(a, p - 1, p), 1, "{}^(p-1)", a);
+            assert_eq!(mod_pow(a, p - 2, p) * a % p, 1, "{} times its inverse", a);
+        }
+        assert_eq!(mod_pow(2_u64, 1 << 40, 65521), mod_pow(mod_pow(2_u64, 1 << 20, 65521), 1 << 20, 65521));
+    }
+
+    #[test]
+    fn mod_inverse_of_small_primes() {
+        assert_eq!(mod_inverse(3_u32, 11), Some(4));
+        assert_eq!(mod_inverse(2_u32, 4), None);
+        assert_eq!(mod_inverse(0_u32, 7), None);
+        for &p in &[2_u64, 3, 5, 11, 97, 32749] {
+            for a in 1..p.min(500) {
+                let inv = mod_inverse(a, p).unwrap();
+                assert_eq!(a * inv % p, 1, "{}^-1 % {}", a, p);
+            }
+        }
+    }
+
+    #[test]
+    fn mod_inverse_mod_32768_exists_for_odd_numbers() {
+        for a in 0..200_u32 {
+            match mod_inverse(a, 32768) {
+                Some(inv) => assert_eq!(a * inv % 32768, 1, "{}", a),
+                None => assert_eq!(a % 2, 0, "{}", a),
+            }
+        }
+    }
+
+    #[test]
+    fn mod_mul_exact_doesnt_overflow_u64() {
+        let cases = [
+            (u64::MAX, u64::MAX, u64::MAX - 58),
+            (u64::MAX - 1, 3, 1_000_000_007),
+            (1 << 63, 1 << 63, (1 << 61) - 1),
+            (123_456_789_012_345, 987_654_321_098_765, u64::MAX),
+            (5, 7, 1),
+        ];
+        for &(a, b, m) in &cases {
+            let expected = (u128::from(a) * u128::from(b) % u128::from(m)) as u64;
+            assert_eq!(mod_mul_exact(a, b, m), expected, "{}*{}%{}", a, b, m);
+        }
+        // Where the product overflows, the fast path's wrapped answer is wrong.
+        let (a, b, m) = cases[0];
+        assert_ne!(mod_mul(a, b, m), mod_mul_exact(a, b, m));
+        assert_eq!(mod_mul_exact(6_u16, 7, 32768), mod_mul_u16(6, 7));
+    }
+
+    #[test]
+    fn mod_mul_u16_matches_u64_reference() {
+        for &a in &[0, 1, 2, 181, 255, 32767, 40000, 65535] {
+            for &b in &[0, 1, 3, 182, 256, 32767, 50000, 65535] {
+                assert_eq!(u64::from(mod_mul_u16(a, b)), u64::from(a) * u64::from(b) % 32768,
+                           "{}*{}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use serde::{Serialize, Deserialize};
+use crate::{Snapshot, Vm};
+
+/// Bumped whenever the layout of `Session` changes, so old files fail to load cleanly.
+pub const SESSION_VERSION: u32 = 3;
+
+/// Everything the debugger accumulates between prompts, so a session can be resumed later.
+#[derive(Serialize, Deserialize)]
+pub struct Session<'a> {
+    pub version: u32,
+    pub step_no: usize,
+    pub vm: Vm<'a>,
+    pub by_step: HashMap<usize, Snapshot>,
+    #[serde(default)]
+    pub names: HashMap<String, Snapshot>,
+    pub all_input: String,
+}
+
+impl<'a> Session<'a> {
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+    pub fn load(path: &str) -> io::Result<Session<'static>> {
+        let reader = BufReader::new(File::open(path)?);
+        let value: serde_json::Value = serde_json::from_reader(reader).map_err(io::Error::from)?;
+        let version = value.get("version").and_then(|v| v.as_u64());
+        if version != Some(SESSION_VERSION.into()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("session version {:?} is not supported (expected {})",
+                                              version, SESSION_VERSION)));
+        }
+        serde_json::from_value(value).map_err(io::Error::from)
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeSet;
+use crate::{DecodedOp, Op, Vm};
+
+/// How far into a routine to look for the Ackermann shape.
+const BODY_LEN: usize = 32;
+
+/// A routine that looks like the teleporter's Ackermann-style confirmation function.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AckCandidate {
+    pub entry: u16,
+    /// How many times the routine calls itself.
+    pub self_calls: usize,
+    /// Which of the telltale features were seen, out of `FEATURES`.
+    pub features: Vec<&'static str>,
+}
+
+pub const FEATURES: usize = 4;
+
+impl AckCandidate {
+    pub fn confidence(&self) -> &'static str {
+        match self.features.len() {
+            FEATURES => "high",
+            2..=3 => "medium",
+            _ => "low",
+        }
+    }
+}
+
+/// The instructions from `entry` by linear sweep, stopping at `Halt`, a non-opcode, or the end of
+/// memory. Each is its address, op and raw operands.
+fn body(vm: &Vm, entry: u16) -> Vec<(u16, Op, Vec<u16>)> {
+    let mut ans = Vec::new();
+    let mut ip = entry;
+    while ans.len() < BODY_LEN {
+        let op = match vm.try_get(ip).map(DecodedOp::from) {
+            Some(DecodedOp::Known(Op::Halt)) | Some(DecodedOp::Unknown(_)) | None => break,
+            Some(DecodedOp::Known(op)) => op,
+        };
+        let args = vm.read_mem_slice(ip + 1, Vm::arg_count(op));
+        if args.len() < usize::from(Vm::arg_count(op)) {
+            break;
+        }
+        ans.push((ip, op, args));
+        ip += 1 + Vm::arg_count(op);
+    }
+    ans
+}
+
+/// Finds routines shaped like `ack(r0, r1)` with `c` in r7: they call themselves, branch on r0 and
+/// r1 being zero, return `r1 + 1` in the base case, and read r7. Any `17 <addr>` pair in memory is
+/// taken as a possible call, since the game reaches most of its code through register jumps that
+/// `reachable_code` can't follow. Best candidates first.
+pub fn find_ack_routines(vm: &Vm) -> Vec<AckCandidate> {
+    let targets: BTreeSet<u16> = (0..vm.rom.len().min(32768) as u16)
+        .filter(|&a| vm.try_get(a) == Some(Op::Call as u16))
+        .filter_map(|a| vm.try_get(a + 1))
+        .filter(|&t| t < 32768)
+        .collect();
+    let mut ans: Vec<AckCandidate> = targets.into_iter()
+        .filter_map(|entry| {
+            let body = body(vm, entry);
+            let self_calls = body.iter().filter(|(_, op, args)| *op == Op::Call && args[0] == entry).count();
+            if self_calls == 0 {
+                return None;
+            }
+            let branches_on = |reg: u16| body.iter().any(|(_, op, args)| (*op == Op::Jt || *op == Op::Jf) && args[0] == reg);
+            let mut features = Vec::new();
+            if branches_on(32768) {
+                features.push("branches on r0");
+            }
+            if branches_on(32769) {
+                features.push("branches on r1");
+            }
+            if body.iter().any(|(_, op, args)| *op == Op::Add && args[..] == [32768, 32769, 1]) {
+                features.push("returns r1 + 1");
+            }
+            if body.iter().any(|(_, _, args)| args.contains(&32775)) {
+                features.push("reads r7");
+            }
+            Some(AckCandidate { entry, self_calls, features })
+        })
+        .collect();
+    ans.sort_by_key(|c| std::cmp::Reverse(c.features.len()));
+    ans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn finds_the_routine_in_the_challenge() {
+        let program = crate::load_program("doc/challenge.bin").unwrap();
+        let mut vm = Vm::new(&program);
+        vm.live_output = false;
+        vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap();
+        let found = find_ack_routines(&vm);
+        assert_eq!(found[0].entry, 6027);
+        assert_eq!(found[0].self_calls, 3);
+        assert_eq!(found[0].confidence(), "high");
+    }
+}
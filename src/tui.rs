@@ -0,0 +1,134 @@
+use std::io;
+use std::io::{stdout, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use crate::{RunStatus, Vm};
+
+/// Width of the register/stack pane on the right.
+const SIDE_WIDTH: u16 = 24;
+/// Height of the disassembly pane above the input line.
+const DISASM_HEIGHT: u16 = 10;
+
+/// Splits `text` into lines of at most `width` chars.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    text.lines()
+        .flat_map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                vec![String::new()]
+            } else {
+                chars.chunks(width).map(|c| c.iter().collect()).collect()
+            }
+        })
+        .collect()
+}
+
+fn clip(line: &str, width: u16) -> String {
+    line.chars().take(usize::from(width)).collect()
+}
+
+fn draw<W: Write>(out: &mut W, vm: &Vm, log: &str, input: &str) -> io::Result<()> {
+    let (cols, rows) = terminal::size()?;
+    let side = SIDE_WIDTH.min(cols / 3);
+    let main_width = cols.saturating_sub(side + 1);
+    let disasm_height = DISASM_HEIGHT.min(rows / 3);
+    let output_height = rows.saturating_sub(disasm_height + 2);
+    queue!(out, Clear(ClearType::All))?;
+
+    let wrapped = wrap(log, usize::from(main_width));
+    let shown = wrapped.len().saturating_sub(usize::from(output_height));
+    for (i, line) in wrapped[shown..].iter().enumerate() {
+        queue!(out, MoveTo(0, i as u16), Print(line))?;
+    }
+
+    let mut side_lines = vec![format!("IP {}", vm.instruction_pointer)];
+    side_lines.extend(vm.registers().iter().enumerate().map(|(r, value)| format!("r{} {}", r, value)));
+    side_lines.push(format!("stack ({}), top first:", vm.stack.len()));
+    side_lines.extend(vm.stack.iter().rev().map(|v| format!("  {}", v)));
+    for row in 0..output_height {
+        queue!(out, MoveTo(main_width, row), Print("|"))?;
+        if let Some(line) = side_lines.get(usize::from(row)) {
+            queue!(out, Print(clip(line, side)))?;
+        }
+    }
+
+    queue!(out, MoveTo(0, output_height), Print("-".repeat(usize::from(cols))))?;
+    let mut ip = vm.instruction_pointer;
+    for row in 0..disasm_height {
+        match vm.disassemble_one(ip) {
+            Some((line, next)) => {
+                queue!(out, MoveTo(0, output_height + 1 + row), Print(clip(&line, cols)))?;
+                ip = next;
+            }
+            None => break,
+        }
+    }
+
+    queue!(out, MoveTo(0, rows.saturating_sub(1)), Print(clip(&format!("> {}", input), cols)))?;
+    out.flush()
+}
+
+fn event_loop<W: Write>(out: &mut W, vm: &mut Vm, log: &mut String) -> io::Result<()> {
+    let mut input = String::new();
+    loop {
+        draw(out, vm, log, &input)?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Enter => {
+                *log += &input;
+                *log += "\n";
+                input.push('\n');
+                vm.push_input(&input);
+                input.clear();
+                match vm.run_to_input(Arc::new(AtomicBool::new(true))) {
+                    Ok(RunStatus::Halted) => *log += "[halted]\n",
+                    Ok(_) => (),
+                    Err(e) => *log += &format!("[VM error: {}]\n", e),
+                }
+                *log += &vm.take_output();
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => (),
+        }
+    }
+}
+
+/// Runs the game full-screen, with panes for its output, the registers and stack, and the
+/// disassembly from the IP. Lines typed at the bottom are fed to the game; Esc or Ctrl-C leaves.
+pub fn run(vm: &mut Vm) -> io::Result<()> {
+    let live_output = vm.live_output;
+    vm.live_output = false;
+    let mut log = vm.take_output();
+    let mut out = stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen)?;
+    let result = event_loop(&mut out, vm, &mut log);
+    execute!(out, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    vm.live_output = live_output;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_splits_long_lines_and_keeps_blank_ones() {
+        assert_eq!(wrap("abcdef\n\nxy", 4), vec!["abcd", "ef", "", "xy"]);
+    }
+}
@@ -12,9 +12,6 @@
 
 */
 
-use pathfinding::prelude::*;
-use crate::Op;
-
 #[derive(Copy,Clone,Debug,Hash,PartialEq,Eq)]
 pub enum OrbColour {
     GreenForAdd,
@@ -34,67 +31,312 @@ pub struct OrbState {
  +   4 -  18
  =22 - 9  *
  */
-pub fn get_room_value(p: (u8,u8)) -> Option<i32> {
-    match p {
-        (0,0) => Some(22),
-        (0,2) => Some(9),
-        (1,1) => Some(4),
-        (1,3) => Some(18),
-        (2,0) => Some(4),
-        (2,2) => Some(11),
-        (3,1) => Some(8),
-        (3,3) => Some(1),
-        _ => None
-    }
+/// What occupies one room of the vault antechamber.
+#[derive(Copy,Clone,Debug,Hash,PartialEq,Eq)]
+pub enum Cell {
+    Value(i32),
+    Orb(OrbColour),
+    /// Where the orb is picked up; its value is the orb's starting weight.
+    Start(i32),
+    /// The vault door, which the orb must reach weighing 30.
+    Vault(i32),
 }
-pub fn get_room_orb_colour(p: (u8,u8)) -> Option<OrbColour> {
-    match p {
-        (0,1) | (1,2) | (3,2) => Some(OrbColour::RedForSub),
-        (0,3) | (2,1) | (2,3) | (3,0) => Some(OrbColour::OrangeForMult),
-        (1,0) => Some(OrbColour::GreenForAdd),
-        _ => None
+/// A 4x4 layout of rooms, indexed `[y][x]` to match the `(y,x)` positions used elsewhere.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct VaultGrid {
+    pub cells: [[Cell; 4]; 4],
+    /// The heaviest the orb may get. The orb shatters if it gets this heavy, or down to 0.
+    pub max_weight: i32,
+    /// What the orb weighs when picked up.
+    pub start_value: i32,
+    /// What the orb must weigh on reaching the vault.
+    pub goal_value: i32,
+}
+impl VaultGrid {
+    /// A grid with the weight capped at 32767, the most the VM's registers can hold, and the orb
+    /// taken from 22 to 30 as in the challenge.
+    pub fn new(cells: [[Cell; 4]; 4]) -> Self {
+        VaultGrid { cells, max_weight: 32767, start_value: 22, goal_value: 30 }
+    }
+    pub fn with_values(self, start_value: i32, goal_value: i32) -> Self {
+        VaultGrid { start_value, goal_value, ..self }
+    }
+    pub fn with_max_weight(self, max_weight: i32) -> Self {
+        VaultGrid { max_weight, ..self }
+    }
+    /// The layout in the challenge, as in the diagram at the top of the file.
+    pub fn challenge() -> Self {
+        use Cell::*;
+        use OrbColour::*;
+        VaultGrid::new([
+            [Start(22), Orb(RedForSub), Value(9), Orb(OrangeForMult)],
+            [Orb(GreenForAdd), Value(4), Orb(RedForSub), Value(18)],
+            [Value(4), Orb(OrangeForMult), Value(11), Orb(OrangeForMult)],
+            [Orb(OrangeForMult), Value(8), Orb(RedForSub), Vault(1)],
+        ])
+    }
+    fn cell(&self, (y,x): (u8,u8)) -> Option<Cell> {
+        self.cells.get(usize::from(y))?.get(usize::from(x)).cloned()
+    }
+    pub fn get_room_value(&self, p: (u8,u8)) -> Option<i32> {
+        match self.cell(p)? {
+            Cell::Value(v) | Cell::Start(v) | Cell::Vault(v) => Some(v),
+            Cell::Orb(_) => None,
+        }
+    }
+    pub fn get_room_orb_colour(&self, p: (u8,u8)) -> Option<OrbColour> {
+        match self.cell(p)? {
+            Cell::Orb(c) => Some(c),
+            _ => None,
+        }
+    }
+    /// The orb as it's picked up, in the antechamber.
+    pub fn start(&self) -> OrbState {
+        OrbState { position: (0,0), value: self.start_value, colour: None }
+    }
+    pub fn goal(&self, s: &OrbState) -> bool {
+        s.position == (3,3) && s.value == self.goal_value
+    }
+    pub fn do_step(&self, s: &OrbState, p: (u8,u8)) -> OrbState {
+        let val = self.get_room_value(p);
+        let mut ans = *s;
+        ans.position = p;
+        //println!("step to {:?} from {:?} = ",p,s);
+        ans.colour = self.get_room_orb_colour(p);
+        match s.colour {
+            None => (),
+            Some(OrbColour::GreenForAdd) => {
+                ans.value += val.expect("Room should be valued")
+            },
+            Some(OrbColour::OrangeForMult) => ans.value *= val.expect("Room should be valued"),
+            Some(OrbColour::RedForSub) => ans.value -= val.expect("Room should be valued"),
+        }
+        //println!("      {:?}",ans);
+        ans
+    }
+    pub fn neighbours(&self, s: &OrbState) -> Vec<OrbState> {
+        let (y,x) = (i16::from(s.position.0), i16::from(s.position.1));
+        let all_pos = [(y+1,x),(y-1,x),(y,x-1),(y,x+1)];
+        all_pos.iter()
+            .filter(|&&(y,x)| (0..4).contains(&y) && (0..4).contains(&x))
+            .map(|&(y,x)| (y as u8, x as u8))
+            .filter(|&p| p != (0,0))
+            // An operator has to be followed by a number, so one orb room can't lead to another.
+            .filter(|&p| s.colour.is_none() || self.get_room_value(p).is_some())
+            .map(|p| self.do_step(s,p))
+            .filter(|s| s.value > 0 && s.value <= self.max_weight)
+            .filter(|s| s.position != (3,3) || s.value == self.goal_value)
+            .collect()
+    }
+    /// A shortest route to the vault by breadth-first search, as a direction string.
+    pub fn shortest_solution(&self) -> Option<String> {
+        let path = pathfinding::directed::bfs::bfs(&self.start(), |s| self.neighbours(s), |s| self.goal(s))?;
+        Some(directions(&path).expect("solver made an illegal move"))
+    }
+    /// A shortest route to the vault by A*, steering towards the vault by Manhattan distance, as a
+    /// direction string and its cost of one per move.
+    pub fn astar_solution(&self) -> Option<(String, usize)> {
+        let to_vault = |s: &OrbState| usize::from(3 - s.position.0) + usize::from(3 - s.position.1);
+        let (path, cost) = pathfinding::directed::astar::astar(
+            &self.start(),
+            |s| self.neighbours(s).into_iter().map(|n| (n, 1)),
+            to_vault,
+            |s| self.goal(s))?;
+        Some((directions(&path).expect("solver made an illegal move"), cost))
+    }
+    /// Every shortest route to the vault, as direction strings in sorted order.
+    pub fn all_shortest_solutions(&self) -> Vec<String> {
+        let paths = pathfinding::directed::astar::astar_bag_collect(
+            &self.start(),
+            |s| self.neighbours(s).into_iter().map(|n| (n, 1)),
+            |_| 0,
+            |s| self.goal(s));
+        let mut ans = paths.map(|(paths, _)| paths.iter().map(|p| directions(p).expect("solver made an illegal move")).collect()).unwrap_or_else(Vec::new);
+        ans.sort();
+        ans
     }
 }
-pub fn do_step(s: &OrbState, p: (u8,u8)) -> OrbState {
-    let val = get_room_value(p);
-    let mut ans = s.clone();
-    ans.position = p;
-    //println!("step to {:?} from {:?} = ",p,s);
-    ans.colour = get_room_orb_colour(p);
-    match s.colour {
-        None => (),
-        Some(OrbColour::GreenForAdd) => {
-            ans.value += val.expect("Room should be valued")
-        },
-        Some(OrbColour::OrangeForMult) => ans.value *= val.expect("Room should be valued"),
-        Some(OrbColour::RedForSub) => ans.value -= val.expect("Room should be valued"),
-    }
-    //println!("      {:?}",ans);
-    ans
+/// Why a path can't be walked in the game. `step` is the index of the offending move.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum InvalidPath {
+    /// The move isn't to one of the four neighbouring rooms.
+    NotAdjacent { step: usize, from: (u8,u8), to: (u8,u8) },
+    /// The move leaves the 4x4 grid.
+    OutOfBounds { step: usize, to: (u8,u8) },
+    /// The move goes back to the antechamber, which resets the orb.
+    IntoAntechamber { step: usize },
 }
-pub fn neighbours(s: &OrbState) -> Vec<OrbState> {
-    let (y,x) = s.position;
-    let all_pos = vec![(y+1,x),(y-1,x),(y,x-1),(y,x+1)];
-    all_pos.into_iter()
-        .filter(|&p| p.0 >= 0 && p.1 >= 0 && p.0 < 4 && p.1 < 4 && p != (0,0))
-        .map(|p| do_step(s,p))
-        .filter(|s| s.position != (3,3) || s.value == 30)
+/// The compass letters for each move along `path`; north is increasing y, east increasing x.
+/// Fails on the first move that isn't a single step to a neighbouring room the orb may enter.
+pub fn directions(path: &[OrbState]) -> Result<String, InvalidPath> {
+    path.windows(2)
+        .enumerate()
+        .map(|(step, w)| {
+            let ((y0, x0), (y1, x1)) = (w[0].position, w[1].position);
+            if y1 >= 4 || x1 >= 4 {
+                return Err(InvalidPath::OutOfBounds { step, to: (y1, x1) });
+            }
+            if (y1, x1) == (0,0) {
+                return Err(InvalidPath::IntoAntechamber { step });
+            }
+            match (i16::from(y1) - i16::from(y0), i16::from(x1) - i16::from(x0)) {
+                (1, 0) => Ok('N'),
+                (-1, 0) => Ok('S'),
+                (0, 1) => Ok('E'),
+                (0, -1) => Ok('W'),
+                _ => Err(InvalidPath::NotAdjacent { step, from: (y0, x0), to: (y1, x1) }),
+            }
+        })
         .collect()
 }
-pub fn goal(s: &OrbState) -> bool {
-    s.position == (3,3) && s.value == 30
+/// `VaultGrid::all_shortest_solutions` for the challenge's layout.
+pub fn all_shortest_solutions() -> Vec<String> {
+    VaultGrid::challenge().all_shortest_solutions()
 }
-pub fn find_sol() {
-    let foo= pathfinding::directed::bfs::bfs(&OrbState{
-        position: (0,0),
-        value: 22,
-        colour: None
-    },neighbours, goal);
-    println!("{:?}",foo);
+/// Prints the challenge's layout and routes through it for an orb going from `start_value` to
+/// `goal_value`.
+pub fn find_sol(start_value: i32, goal_value: i32) {
+    let grid = VaultGrid::challenge().with_values(start_value, goal_value);
+    for row in grid.cells.iter().rev() {
+        println!("{:?}", row);
+    }
+    match grid.shortest_solution() {
+        Some(moves) => println!("Shortest: {} ({} moves)", moves, moves.len()),
+        None => println!("No route to the vault"),
+    }
+    if let Some((moves, cost)) = grid.astar_solution() {
+        println!("A*: {} (cost {})", moves, cost);
+    }
+    println!("All shortest: {:?}", grid.all_shortest_solutions());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_shortest_solutions_finds_the_unique_route() {
+        // The first route in the notes below steps into the vault at 31 on the way, which the
+        // game rejects, so only the second one survives.
+        assert_eq!(all_shortest_solutions(), vec!["NEENWSEEWNNE".to_string()]);
+    }
+
+    #[test]
+    fn shortest_solution_is_a_move_string() {
+        assert_eq!(VaultGrid::challenge().shortest_solution(), Some("NEENWSEEWNNE".to_string()));
+    }
+
+    #[test]
+    fn astar_finds_a_route_as_short_as_bfs() {
+        let grid = VaultGrid::challenge();
+        let (moves, cost) = grid.astar_solution().unwrap();
+        assert_eq!(cost, moves.len());
+        assert_eq!(cost, grid.shortest_solution().unwrap().len());
+    }
+
+    #[test]
+    fn neighbours_of_the_corner_stay_on_the_grid() {
+        let start = OrbState { value: 22, colour: None, position: (0,0) };
+        let next = VaultGrid::challenge().neighbours(&start);
+        assert_eq!(next.iter().map(|s| s.position).collect::<Vec<_>>(), vec![(1,0), (0,1)]);
+    }
+
+    #[test]
+    fn the_route_keeps_the_orb_whole() {
+        let grid = VaultGrid::challenge();
+        let mut orb = OrbState { value: 22, colour: None, position: (0,0) };
+        for dir in "NEENWSEEWNNE".chars() {
+            let (y, x) = orb.position;
+            let to = match dir {
+                'N' => (y + 1, x),
+                'S' => (y - 1, x),
+                'E' => (y, x + 1),
+                _ => (y, x - 1),
+            };
+            orb = grid.do_step(&orb, to);
+            assert!(orb.value > 0, "orb shattered at {:?}", orb);
+        }
+        assert!(grid.goal(&orb));
+        // Capping the weight below the 60 the route passes through forces a longer way round.
+        let capped = grid.with_max_weight(59).all_shortest_solutions();
+        assert!(!capped.is_empty());
+        assert!(capped.iter().all(|route| route.len() == 14));
+    }
+
+    #[test]
+    fn adjacent_orb_rooms_are_not_a_move() {
+        use Cell::*;
+        use OrbColour::*;
+        let grid = VaultGrid::new([
+            [Start(22), Orb(GreenForAdd), Orb(RedForSub), Value(1)],
+            [Value(1), Value(1), Value(1), Value(1)],
+            [Value(1), Value(1), Value(1), Value(1)],
+            [Value(1), Value(1), Value(1), Vault(1)],
+        ]);
+        let on_orb = OrbState { value: 22, colour: Some(GreenForAdd), position: (0,1) };
+        let next = grid.neighbours(&on_orb);
+        assert_eq!(next.iter().map(|s| s.position).collect::<Vec<_>>(), vec![(1,1)]);
+    }
+
+    fn at(positions: &[(u8,u8)]) -> Vec<OrbState> {
+        positions.iter().map(|&position| OrbState { value: 0, colour: None, position }).collect()
+    }
+
+    #[test]
+    fn directions_of_the_noted_solutions() {
+        // The positions of the two paths in the notes below.
+        let first = at(&[(0,0), (1,0), (1,1), (1,2), (2,2), (3,2), (2,2), (2,1), (3,1), (3,2), (3,3), (3,2), (3,3)]);
+        assert_eq!(directions(&first), Ok("NEENNSWNEEWE".to_string()));
+        let second = at(&[(0,0), (1,0), (1,1), (1,2), (2,2), (2,1), (1,1), (1,2), (1,3), (1,2), (2,2), (3,2), (3,3)]);
+        assert_eq!(directions(&second), Ok("NEENWSEEWNNE".to_string()));
+    }
+
+    #[test]
+    fn directions_rejects_illegal_moves() {
+        assert_eq!(directions(&at(&[(0,0), (1,1)])), Err(InvalidPath::NotAdjacent { step: 0, from: (0,0), to: (1,1) }));
+        assert_eq!(directions(&at(&[(0,0), (1,0), (1,0)])), Err(InvalidPath::NotAdjacent { step: 1, from: (1,0), to: (1,0) }));
+        assert_eq!(directions(&at(&[(0,0), (1,0), (0,0)])), Err(InvalidPath::IntoAntechamber { step: 1 }));
+        assert_eq!(directions(&at(&[(3,3), (4,3)])), Err(InvalidPath::OutOfBounds { step: 0, to: (4,3) }));
+    }
+
+    #[test]
+    fn a_synthetic_grid_is_solved_too() {
+        use Cell::*;
+        use OrbColour::*;
+        // 22 + 4 + 2 + 2: the only way to 30 in six moves is up the left side and along the top.
+        let grid = VaultGrid::new([
+            [Start(22), Value(1), Value(1), Value(1)],
+            [Orb(GreenForAdd), Value(1), Value(1), Value(1)],
+            [Value(4), Value(1), Value(1), Value(1)],
+            [Orb(GreenForAdd), Value(2), Orb(GreenForAdd), Vault(2)],
+        ]);
+        assert_eq!(grid.get_room_value((2,0)), Some(4));
+        assert_eq!(grid.get_room_orb_colour((3,2)), Some(GreenForAdd));
+        assert_eq!(grid.all_shortest_solutions(), vec!["NNNEEE".to_string()]);
+        assert_eq!(grid.with_values(10, 18).all_shortest_solutions(), vec!["NNNEEE".to_string()]);
+        // Ending where it started means not picking up anything on the way.
+        assert!(grid.with_values(10, 10).all_shortest_solutions().iter().all(|route| route.starts_with('E')));
+    }
+
+    #[test]
+    fn vault_grid_matches_the_diagram() {
+        use Cell::*;
+        use OrbColour::*;
+        // The diagram at the top of the file has north at the top, so its rows run y=3 down to y=0.
+        let diagram = [
+            [Orb(OrangeForMult), Value(8), Orb(RedForSub), Vault(1)],
+            [Value(4), Orb(OrangeForMult), Value(11), Orb(OrangeForMult)],
+            [Orb(GreenForAdd), Value(4), Orb(RedForSub), Value(18)],
+            [Start(22), Orb(RedForSub), Value(9), Orb(OrangeForMult)],
+        ];
+        let mut grid = VaultGrid::challenge().cells;
+        grid.reverse();
+        assert_eq!(grid, diagram);
+    }
 }
 
 /*
-NEENNSWNEE
+NEENNSWNEEWE (not valid: enters the vault at 31 after 10 moves)
 Some([
 OrbState { value: 22, colour: None, position: (0, 0) },
 OrbState { value: 22, colour: Some(GreenForAdd), position: (1, 0) },
@@ -0,0 +1,37 @@
+//! Driving the machine from canned input alone, as a library user would: headless, so an empty
+//! queue hands control back instead of reading stdin.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use synacor_challenge::{RunStatus, Vm};
+
+fn run(vm: &mut Vm) -> RunStatus {
+    vm.run_to_input(Arc::new(AtomicBool::new(true))).unwrap()
+}
+
+#[test]
+fn echoes_what_is_fed() {
+    // in r0; out r0; jmp 0
+    let program = [20, 32768, 19, 32768, 6, 0];
+    let mut vm = Vm::new(&program);
+    vm.set_live_output(false);
+    vm.set_headless(true);
+    assert_eq!(run(&mut vm), RunStatus::WaitingForInput);
+    vm.feed_str("hi\n");
+    assert_eq!(run(&mut vm), RunStatus::WaitingForInput);
+    assert_eq!(vm.take_output(), "hi\n");
+}
+
+#[test]
+fn plays_the_challenge() {
+    let mut vm = Vm::from_file("doc/challenge.bin").unwrap();
+    vm.set_live_output(false);
+    vm.set_headless(true);
+    assert_eq!(run(&mut vm), RunStatus::WaitingForInput);
+    assert!(vm.take_output().contains("What do you do?"));
+    vm.set_input_queue("take tablet\nlook tablet\n");
+    assert_eq!(run(&mut vm), RunStatus::WaitingForInput);
+    let output = vm.take_output();
+    assert!(output.contains("Taken."), "{}", output);
+    assert!(output.contains("writing surface"), "{}", output);
+}